@@ -1,32 +1,180 @@
-use serde::{Deserialize, Serialize};
-use serde_json::{Map, Value};
+use log::warn;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+use crate::{
+    grade::{HtmlGrade, LetterGrade},
+    instance_info::{InstanceInfo, Instances, TimedRequest, TimingStats},
+    proxy::NORMAL_NETWORK,
+    version::{Version, VersionRange},
+};
+
+/// Criteria per searx.space timing bucket. Each one is either a [`TimingCriteria`]
+/// or, as in older configs, just the maximum mean in seconds.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Timings {
-    pub search: Option<f32>,
-    pub google: Option<f32>,
-    pub wikipedia: Option<f32>,
-    pub initial: Option<f32>,
+    #[serde(default, deserialize_with = "mean_or_criteria")]
+    pub search: Option<TimingCriteria>,
+    #[serde(default, alias = "google", deserialize_with = "mean_or_criteria")]
+    pub search_go: Option<TimingCriteria>,
+    #[serde(default, alias = "wikipedia", deserialize_with = "mean_or_criteria")]
+    pub search_wp: Option<TimingCriteria>,
+    #[serde(default, deserialize_with = "mean_or_criteria")]
+    pub initial: Option<TimingCriteria>,
+}
+
+/// Times are maximums in seconds, compared exclusively. Instances missing a
+/// statistic that has a threshold don't pass.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TimingCriteria {
+    pub max_mean: Option<f64>,
+    pub max_median: Option<f64>,
+    pub max_stdev: Option<f64>,
+    /// Minimum `success_percentage`, 0 to 100.
+    pub min_success: Option<f64>,
+}
+
+impl TimingCriteria {
+    pub fn max_mean(max_mean: f64) -> Self {
+        Self {
+            max_mean: Some(max_mean),
+            ..Self::default()
+        }
+    }
+
+    fn accepts(&self, stats: Option<&TimingStats>) -> bool {
+        let values = stats.and_then(|stats| stats.all.as_ref());
+        let below = |maximum: Option<f64>, value: Option<f64>| {
+            maximum.is_none_or(|maximum| value.is_some_and(|value| value < maximum))
+        };
+        below(self.max_mean, stats.and_then(TimingStats::mean))
+            && below(self.max_median, values.and_then(|values| values.median))
+            && below(self.max_stdev, values.and_then(|values| values.stdev))
+            && self.min_success.is_none_or(|minimum| {
+                stats
+                    .and_then(|stats| stats.success_percentage)
+                    .is_some_and(|success| success >= minimum)
+            })
+    }
+}
+
+fn mean_or_criteria<'de, D>(deserializer: D) -> Result<Option<TimingCriteria>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MeanOrCriteria {
+        MaxMean(f64),
+        Criteria(TimingCriteria),
+    }
+    let criteria = Option::<MeanOrCriteria>::deserialize(deserializer)?;
+    Ok(criteria.map(|criteria| match criteria {
+        MeanOrCriteria::MaxMean(max_mean) => TimingCriteria::max_mean(max_mean),
+        MeanOrCriteria::Criteria(criteria) => criteria,
+    }))
 }
 
 #[derive(Default, Debug, Deserialize, Serialize, Clone)]
 pub struct Filter {
     pub response_times: Option<Timings>,
+    /// Accepted searx.space `html.grade`s. Takes precedence over `min_grade`.
     pub grades: Option<Vec<String>>,
+    /// Worst accepted `html.grade`, [`HtmlGrade::DEFAULT_MINIMUM`] when neither this nor
+    /// `grades` is set. `V` keeps vanilla instances only, `Cjs` allows client-side JS changes.
+    pub min_grade: Option<HtmlGrade>,
+    /// Minimum and maximum version, see [`VersionRange::parse`].
     pub versions: Option<(String, String)>,
+    /// searx.space `network_type`s to accept, `normal` only when unset.
+    pub networks: Option<Vec<String>>,
+    /// Instances kept regardless of grade and timings. Entries are exact URLs
+    /// (`https://searx.be/`), domain suffixes (`example.org`) or globs (`*.searx.*`).
+    pub allow: Option<Vec<String>>,
+    /// Instances never kept, even when allowed. Same entry forms as `allow`.
+    pub deny: Option<Vec<String>>,
+    /// Minimum searx.space `tls.grade`.
+    pub min_tls_grade: Option<LetterGrade>,
+    /// Minimum searx.space `csp.grade`.
+    pub min_csp_grade: Option<LetterGrade>,
+    /// Minimum searx.space `http.grade`, the Mozilla Observatory rating of the headers.
+    pub min_http_grade: Option<LetterGrade>,
+    pub min_uptime: Option<UptimeCriteria>,
+    /// Required value of the searx.space `analytics` flag, `false` drops instances using analytics.
+    pub analytics: Option<bool>,
+    pub asn_privacy: Option<AsnPrivacy>,
+    /// Required IPv6 availability.
+    pub ipv6: Option<bool>,
+    /// Required value of the `main` flag of official instances. Unflagged ones count as `false`.
+    pub main: Option<bool>,
+}
+
+/// Accepted searx.space `network.asn_privacy` ratings of the hosting ASN.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AsnPrivacy {
+    /// Known not to be privacy hostile.
+    Good,
+    /// Not known to be privacy hostile, including unrated ones.
+    NotBad,
+}
+
+/// Minimum searx.space uptime percentages per window.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct UptimeCriteria {
+    pub day: Option<f64>,
+    pub week: Option<f64>,
+    pub month: Option<f64>,
+    pub year: Option<f64>,
+    /// What happens to instances without data for a window that has a minimum.
+    pub missing: MissingUptime,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingUptime {
+    #[default]
+    Exclude,
+    Allow,
+}
+
+impl Filter {
+    pub fn networks(&self) -> Vec<String> {
+        self.networks
+            .clone()
+            .unwrap_or_else(|| vec![NORMAL_NETWORK.to_string()])
+    }
+
+    pub fn version_range(&self) -> Result<Option<VersionRange>, String> {
+        self.versions
+            .as_ref()
+            .map(|(min, max)| VersionRange::parse(min, max))
+            .transpose()
+    }
 }
 
-pub fn get_filtered_urls<'a>(
-    instances: &'a Map<String, Value>,
-    filter: &'a Filter,
-) -> Vec<&'a String> {
+pub fn get_filtered_urls<'a>(instances: &'a Instances, filter: &'a Filter) -> Vec<&'a String> {
+    let version_range = filter.version_range().unwrap_or_else(|err| {
+        warn!("ignoring the version filter: {err}");
+        None
+    });
     let best_grade_instance_urls: Vec<&String> = instances
         .iter()
         .filter_map(|instance| {
             // trace!("grade {grade}, network_type {network_type}");
-            if filter_by_grade(instance, filter)
-                && filter_by_timings(instance, filter)
-                && filter_by_network(instance)
+            let is_pinned = instance.1.pinned;
+            let is_allowed = matches_any(instance.0, filter.allow.as_deref());
+            if !matches_any(instance.0, filter.deny.as_deref())
+                && filter_by_network(instance, filter)
+                && (is_pinned
+                    || is_allowed
+                    || (filter_by_grade(instance, filter)
+                        && filter_by_security_grades(instance, filter)
+                        && filter_by_timings(instance, filter)
+                        && filter_by_uptime(instance, filter)
+                        && filter_by_privacy(instance, filter)
+                        && filter_by_version(instance, version_range.as_ref())))
             {
                 Some(instance.0)
             } else {
@@ -37,76 +185,185 @@ pub fn get_filtered_urls<'a>(
     best_grade_instance_urls
 }
 
-type Instance<'a> = (&'a String, &'a Value);
-fn filter_by_grade<'a>(instance: Instance, filter: &'a Filter) -> bool {
-    let (_url, value) = instance;
+type Instance<'a> = (&'a String, &'a InstanceInfo);
+fn filter_by_grade(instance: Instance, filter: &Filter) -> bool {
+    let (_url, info) = instance;
 
-    let grade: String = value["html"]["grade"]
-        .as_str()
-        .unwrap_or_default()
-        .to_string();
+    let grade = info.html_grade().unwrap_or_default();
+    match &filter.grades {
+        Some(grades) => grades.iter().any(|accepted| accepted == grade),
+        None => {
+            let minimum = filter.min_grade.unwrap_or(HtmlGrade::DEFAULT_MINIMUM);
+            grade
+                .parse::<HtmlGrade>()
+                .is_ok_and(|grade| grade >= minimum)
+        }
+    }
+}
+
+/// Instances without a published grade don't pass a minimum.
+fn filter_by_security_grades(instance: Instance, filter: &Filter) -> bool {
+    let (_url, info) = instance;
+    [
+        (info.tls_grade(), filter.min_tls_grade),
+        (info.csp_grade(), filter.min_csp_grade),
+        (info.http_grade(), filter.min_http_grade),
+    ]
+    .into_iter()
+    .all(|(grade, minimum)| match minimum {
+        Some(minimum) => grade
+            .and_then(|grade| grade.parse::<LetterGrade>().ok())
+            .is_some_and(|grade| grade >= minimum),
+        None => true,
+    })
+}
+
+fn filter_by_network(instance: Instance, filter: &Filter) -> bool {
+    let (_url, info) = instance;
     filter
-        .grades
-        .clone()
-        .unwrap_or_else(|| vec!["C".to_string(), "V".to_string()])
-        .contains(&grade)
+        .networks()
+        .iter()
+        .any(|network| network == info.network_type())
+}
+
+/// Instances without a recognizable version don't pass a version range.
+fn filter_by_version(instance: Instance, range: Option<&VersionRange>) -> bool {
+    let range = match range {
+        Some(range) => range,
+        None => return true,
+    };
+    let (_url, info) = instance;
+    info.version
+        .as_deref()
+        .and_then(Version::parse)
+        .is_some_and(|version| range.contains(&version))
 }
 
-fn filter_by_network<'a>(instance: Instance) -> bool {
-    let (_url, value) = instance;
-    let network_type: String = value["network_type"]
-        .as_str()
+fn matches_any(url: &str, patterns: Option<&[String]>) -> bool {
+    patterns
         .unwrap_or_default()
-        .to_string();
-    network_type == "normal"
+        .iter()
+        .any(|pattern| matches_pattern(url, pattern))
 }
 
-fn filter_by_timings<'a>(instance: Instance, filter: &'a Filter) -> bool {
-    let response_times = match filter.response_times.clone() {
+/// Globs and exact URLs containing `://` are matched against the whole URL,
+/// everything else against the host.
+fn matches_pattern(url: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let url = url.to_lowercase();
+    let host = Url::parse(&url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default();
+    let is_glob = pattern.contains(['*', '?']);
+    match (is_glob, pattern.contains("://")) {
+        (true, true) => glob_matches(&pattern, &url),
+        (true, false) => glob_matches(&pattern, &host),
+        (false, true) => url.trim_end_matches('/') == pattern.trim_end_matches('/'),
+        (false, false) => {
+            let suffix = pattern.trim_start_matches('.');
+            !suffix.is_empty() && (host == suffix || host.ends_with(&format!(".{suffix}")))
+        }
+    }
+}
+
+/// `*` matches any run of characters, `?` a single one.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn filter_by_timings(instance: Instance, filter: &Filter) -> bool {
+    let response_times = match &filter.response_times {
         Some(times) => times,
         None => return true,
     };
-    let (_url, value) = instance;
-    let timing_obj: &Value = &value["timing"];
-    let mut is_initial_search_ok = true;
-    let mut is_search_ok = true;
-    let mut is_search_go_ok = true;
-    let mut is_search_wp_ok = true;
-    if let Some(timing) = response_times.search {
-        is_search_ok = timing_obj["search"]["all"]["mean"]
-            .as_f64()
-            .map(|mean| mean < timing as f64)
-            .unwrap_or_default();
-    }
-    if let Some(timing) = response_times.google {
-        is_search_go_ok = timing_obj["search_go"]["all"]["mean"]
-            .as_f64()
-            .map(|mean| mean < timing as f64)
-            .unwrap_or_default();
-    }
-    if let Some(timing) = response_times.wikipedia {
-        is_search_wp_ok = timing_obj["search_wp"]["all"]["mean"]
-            .as_f64()
-            .map(|mean| mean < timing as f64)
-            .unwrap_or_default();
-    }
-    if let Some(timing) = response_times.initial {
-        is_initial_search_ok = timing_obj["initial"]["all"]["mean"]
-            .as_f64()
-            .map(|mean| mean < timing as f64)
-            .unwrap_or_default();
-    }
-    is_initial_search_ok && is_search_ok && is_search_go_ok && is_search_wp_ok
+    let (_url, info) = instance;
+    [
+        (TimedRequest::Search, &response_times.search),
+        (TimedRequest::SearchGo, &response_times.search_go),
+        (TimedRequest::SearchWp, &response_times.search_wp),
+        (TimedRequest::Initial, &response_times.initial),
+    ]
+    .into_iter()
+    .all(|(request, criteria)| match criteria {
+        Some(criteria) => criteria.accepts(info.timing(request)),
+        None => true,
+    })
+}
+
+fn filter_by_uptime(instance: Instance, filter: &Filter) -> bool {
+    let criteria = match &filter.min_uptime {
+        Some(criteria) => criteria,
+        None => return true,
+    };
+    let (_url, info) = instance;
+    let uptime = info.uptime.clone().unwrap_or_default();
+    [
+        (uptime.uptime_day, criteria.day),
+        (uptime.uptime_week, criteria.week),
+        (uptime.uptime_month, criteria.month),
+        (uptime.uptime_year, criteria.year),
+    ]
+    .into_iter()
+    .all(|(uptime, minimum)| match (uptime, minimum) {
+        (_, None) => true,
+        (Some(uptime), Some(minimum)) => uptime >= minimum,
+        (None, Some(_)) => criteria.missing == MissingUptime::Allow,
+    })
+}
+
+/// Instances without the data a criterion asks for don't pass it.
+fn filter_by_privacy(instance: Instance, filter: &Filter) -> bool {
+    let (_url, info) = instance;
+    let network = info.network.clone().unwrap_or_default();
+    let asn_privacy_ok = match filter.asn_privacy {
+        Some(AsnPrivacy::Good) => network.asn_privacy == Some(0),
+        Some(AsnPrivacy::NotBad) => network.asn_privacy.is_some_and(|rating| rating >= 0),
+        None => true,
+    };
+    asn_privacy_ok
+        && filter
+            .analytics
+            .is_none_or(|analytics| info.analytics == Some(analytics))
+        && filter.ipv6.is_none_or(|ipv6| network.ipv6 == Some(ipv6))
+        && filter
+            .main
+            .is_none_or(|main| info.main.unwrap_or_default() == main)
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use serde_json::json;
+    use serde_json::{json, Map, Value};
 
     use super::*;
+    use crate::instance_info::parse_instances;
+
+    pub(crate) fn info(record: Value) -> InstanceInfo {
+        serde_json::from_value(record).unwrap()
+    }
+
     #[test]
     fn filter_by_timings_test() {
-        let json = json!({
+        let json = info(json!({
         "timing": {
             "search": {
                 "all": {
@@ -129,13 +386,13 @@ pub(crate) mod tests {
                 }
             },
         }
-        });
+        }));
         let filter = Filter {
             response_times: Some(Timings {
-                search: Some(0.4f32),
-                google: Some(0.4f32),
-                wikipedia: Some(0.4f32),
-                initial: Some(0.4f32),
+                search: Some(TimingCriteria::max_mean(0.4)),
+                search_go: Some(TimingCriteria::max_mean(0.4)),
+                search_wp: Some(TimingCriteria::max_mean(0.4)),
+                initial: Some(TimingCriteria::max_mean(0.4)),
             }),
             ..Filter::default()
         };
@@ -147,9 +404,9 @@ pub(crate) mod tests {
         let filter = Filter {
             response_times: Some(Timings {
                 search: None,
-                google: Some(0.4f32),
-                wikipedia: Some(0.4f32),
-                initial: Some(0.4f32),
+                search_go: Some(TimingCriteria::max_mean(0.4)),
+                search_wp: Some(TimingCriteria::max_mean(0.4)),
+                initial: Some(TimingCriteria::max_mean(0.4)),
             }),
             ..Filter::default()
         };
@@ -160,10 +417,10 @@ pub(crate) mod tests {
 
         let filter = Filter {
             response_times: Some(Timings {
-                search: Some(0.6f32),
-                google: Some(0.4f32),
-                wikipedia: Some(0.4f32),
-                initial: Some(0.4f32),
+                search: Some(TimingCriteria::max_mean(0.6)),
+                search_go: Some(TimingCriteria::max_mean(0.4)),
+                search_wp: Some(TimingCriteria::max_mean(0.4)),
+                initial: Some(TimingCriteria::max_mean(0.4)),
             }),
             ..Filter::default()
         };
@@ -174,10 +431,10 @@ pub(crate) mod tests {
 
         let filter = Filter {
             response_times: Some(Timings {
-                search: Some(0.6f32),
-                google: Some(0.6f32),
-                wikipedia: Some(0.6f32),
-                initial: Some(0.6f32),
+                search: Some(TimingCriteria::max_mean(0.6)),
+                search_go: Some(TimingCriteria::max_mean(0.6)),
+                search_wp: Some(TimingCriteria::max_mean(0.6)),
+                initial: Some(TimingCriteria::max_mean(0.6)),
             }),
             ..Filter::default()
         };
@@ -197,17 +454,17 @@ pub(crate) mod tests {
     }
     #[test]
     fn filter_by_timings_test_json_bad() {
-        let json = json!({
+        let json = info(json!({
         "wrong": {
             "nothing": "here"
         }
-        });
+        }));
         let filter = Filter {
             response_times: Some(Timings {
-                search: Some(0.4f32),
-                google: Some(0.4f32),
-                wikipedia: Some(0.4f32),
-                initial: Some(0.4f32),
+                search: Some(TimingCriteria::max_mean(0.4)),
+                search_go: Some(TimingCriteria::max_mean(0.4)),
+                search_wp: Some(TimingCriteria::max_mean(0.4)),
+                initial: Some(TimingCriteria::max_mean(0.4)),
             }),
             ..Filter::default()
         };
@@ -216,11 +473,11 @@ pub(crate) mod tests {
         let include = filter_by_timings(instance, &filter);
         assert!(!include);
 
-        let json = json!({
+        let json = info(json!({
         "wrong": {
             "nothing": "here"
         }
-        });
+        }));
         let filter = Filter {
             response_times: None,
             ..Filter::default()
@@ -230,4 +487,351 @@ pub(crate) mod tests {
         let include = filter_by_timings(instance, &filter);
         assert!(include);
     }
+
+    #[test]
+    fn timing_criteria_test() {
+        let url = "url".to_string();
+        let instance = info(json!({
+            "timing": {
+                "search": {
+                    "success_percentage": 95.0,
+                    "all": { "mean": 2.5, "median": 0.6, "stdev": 1.8 }
+                },
+                "initial": { "all": { "value": 0.3 } }
+            }
+        }));
+        let passes = |search: TimingCriteria| {
+            let filter = Filter {
+                response_times: Some(Timings {
+                    search: Some(search),
+                    ..Timings::default()
+                }),
+                ..Filter::default()
+            };
+            filter_by_timings((&url, &instance), &filter)
+        };
+        // a slow outlier pulls the mean up, the median is still fine
+        assert!(!passes(TimingCriteria::max_mean(1.0)));
+        let median = TimingCriteria {
+            max_median: Some(1.0),
+            ..TimingCriteria::default()
+        };
+        assert!(passes(median.clone()));
+        assert!(!passes(TimingCriteria {
+            max_stdev: Some(1.0),
+            ..median.clone()
+        }));
+        assert!(passes(TimingCriteria {
+            min_success: Some(95.0),
+            ..median.clone()
+        }));
+        assert!(!passes(TimingCriteria {
+            min_success: Some(99.0),
+            ..median
+        }));
+
+        let filter = Filter {
+            response_times: Some(Timings {
+                initial: Some(TimingCriteria::max_mean(0.5)),
+                search_wp: Some(TimingCriteria::default()),
+                ..Timings::default()
+            }),
+            ..Filter::default()
+        };
+        assert!(filter_by_timings((&url, &instance), &filter));
+    }
+
+    #[test]
+    fn timings_config_test() {
+        let legacy: Timings =
+            serde_json::from_value(json!({ "search": 0.5, "google": 1.0, "wikipedia": null }))
+                .unwrap();
+        assert_eq!(
+            legacy,
+            Timings {
+                search: Some(TimingCriteria::max_mean(0.5)),
+                search_go: Some(TimingCriteria::max_mean(1.0)),
+                ..Timings::default()
+            }
+        );
+        let criteria: Timings = serde_json::from_value(
+            json!({ "search_wp": { "max_median": 0.8, "min_success": 90 } }),
+        )
+        .unwrap();
+        assert_eq!(
+            criteria.search_wp,
+            Some(TimingCriteria {
+                max_median: Some(0.8),
+                min_success: Some(90.0),
+                ..TimingCriteria::default()
+            })
+        );
+    }
+
+    #[test]
+    fn filter_by_uptime_test() {
+        let url = "url".to_string();
+        let flaky = info(json!({ "uptime": { "uptimeDay": 100.0, "uptimeWeek": 50.0 } }));
+        let unknown = InstanceInfo::default();
+        let passes = |instance: &InstanceInfo, criteria: UptimeCriteria| {
+            let filter = Filter {
+                min_uptime: Some(criteria),
+                ..Filter::default()
+            };
+            filter_by_uptime((&url, instance), &filter)
+        };
+        let day = UptimeCriteria {
+            day: Some(99.0),
+            ..UptimeCriteria::default()
+        };
+        assert!(passes(&flaky, day.clone()));
+        assert!(!passes(
+            &flaky,
+            UptimeCriteria {
+                week: Some(90.0),
+                ..day.clone()
+            }
+        ));
+        assert!(!passes(&unknown, day.clone()));
+        assert!(!passes(
+            &flaky,
+            UptimeCriteria {
+                month: Some(90.0),
+                ..day.clone()
+            }
+        ));
+        assert!(passes(
+            &unknown,
+            UptimeCriteria {
+                missing: MissingUptime::Allow,
+                ..day.clone()
+            }
+        ));
+        assert!(passes(
+            &flaky,
+            UptimeCriteria {
+                month: Some(90.0),
+                missing: MissingUptime::Allow,
+                ..day
+            }
+        ));
+        assert!(filter_by_uptime((&url, &unknown), &Filter::default()));
+    }
+
+    #[test]
+    fn filter_by_privacy_test() {
+        let url = "url".to_string();
+        let private = info(json!({
+            "analytics": false,
+            "main": true,
+            "network": { "ipv6": true, "asn_privacy": 0 }
+        }));
+        let unrated = info(json!({ "analytics": false, "network": { "asn_privacy": 1 } }));
+        let tracking = info(json!({
+            "analytics": true,
+            "network": { "ipv6": false, "asn_privacy": -1 }
+        }));
+        let unknown = InstanceInfo::default();
+        let passing = |filter: Filter| {
+            [&private, &unrated, &tracking, &unknown]
+                .into_iter()
+                .map(|instance| filter_by_privacy((&url, instance), &filter))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(passing(Filter::default()), vec![true; 4]);
+        let no_analytics = Filter {
+            analytics: Some(false),
+            ..Filter::default()
+        };
+        assert_eq!(passing(no_analytics), vec![true, true, false, false]);
+        let good_asn = Filter {
+            asn_privacy: Some(AsnPrivacy::Good),
+            ..Filter::default()
+        };
+        assert_eq!(passing(good_asn), vec![true, false, false, false]);
+        let not_bad_asn = Filter {
+            asn_privacy: Some(AsnPrivacy::NotBad),
+            ..Filter::default()
+        };
+        assert_eq!(passing(not_bad_asn), vec![true, true, false, false]);
+        let ipv6 = Filter {
+            ipv6: Some(true),
+            ..Filter::default()
+        };
+        assert_eq!(passing(ipv6), vec![true, false, false, false]);
+        let unofficial = Filter {
+            main: Some(false),
+            ..Filter::default()
+        };
+        assert_eq!(passing(unofficial), vec![false, true, true, true]);
+    }
+
+    #[test]
+    fn filter_by_network_test() {
+        let url = "url".to_string();
+        let normal = info(json!({ "network_type": "normal" }));
+        let tor = info(json!({ "network_type": "tor" }));
+        let filter = Filter::default();
+        assert!(filter_by_network((&url, &normal), &filter));
+        assert!(!filter_by_network((&url, &tor), &filter));
+
+        let filter = Filter {
+            networks: Some(vec!["tor".to_string()]),
+            ..Filter::default()
+        };
+        assert!(!filter_by_network((&url, &normal), &filter));
+        assert!(filter_by_network((&url, &tor), &filter));
+    }
+
+    #[test]
+    fn get_filtered_urls_keeps_pinned_test() {
+        let mut instances = Map::new();
+        instances.insert(
+            "https://pinned.org/".to_string(),
+            json!({ "network_type": "normal", "rsearx_pinned": true }),
+        );
+        instances.insert(
+            "https://unrated.org/".to_string(),
+            json!({ "network_type": "normal" }),
+        );
+        instances.insert(
+            "http://pinned.onion/".to_string(),
+            json!({ "network_type": "tor", "rsearx_pinned": true }),
+        );
+        let instances = parse_instances(&instances).instances;
+        let filter = Filter::default();
+        let urls = get_filtered_urls(&instances, &filter);
+        assert_eq!(urls, vec!["https://pinned.org/"]);
+    }
+
+    #[test]
+    fn matches_pattern_test() {
+        let url = "https://search.example.org/searx/";
+        assert!(matches_pattern(url, "https://search.example.org/searx"));
+        assert!(!matches_pattern(url, "https://search.example.org/"));
+        assert!(matches_pattern(url, "example.org"));
+        assert!(matches_pattern(url, ".Example.org"));
+        assert!(matches_pattern(url, "search.example.org"));
+        assert!(!matches_pattern(url, "ample.org"));
+        assert!(matches_pattern(url, "*.example.*"));
+        assert!(matches_pattern(url, "search.???????.org"));
+        assert!(!matches_pattern(url, "*.example.com"));
+        assert!(matches_pattern(url, "https://*/searx/"));
+        assert!(!matches_pattern(url, ""));
+    }
+
+    #[test]
+    fn get_filtered_urls_allow_deny_test() {
+        let mut instances = Map::new();
+        for url in [
+            "https://good.org/",
+            "https://ungraded.org/",
+            "https://bad.example.org/",
+        ] {
+            instances.insert(url.to_string(), json!({ "network_type": "normal" }));
+        }
+        instances["https://good.org/"]["html"] = json!({ "grade": "V" });
+        let instances = parse_instances(&instances).instances;
+        let filter = Filter {
+            allow: Some(vec![
+                "ungraded.org".to_string(),
+                "*.example.org".to_string(),
+            ]),
+            deny: Some(vec!["bad.example.org".to_string()]),
+            ..Filter::default()
+        };
+        let urls = get_filtered_urls(&instances, &filter);
+        assert_eq!(urls, vec!["https://good.org/", "https://ungraded.org/"]);
+
+        let filter = Filter {
+            deny: Some(vec!["https://good.org".to_string()]),
+            ..filter
+        };
+        let urls = get_filtered_urls(&instances, &filter);
+        assert_eq!(
+            urls,
+            vec!["https://bad.example.org/", "https://ungraded.org/"]
+        );
+    }
+
+    #[test]
+    fn filter_by_version_test() {
+        let url = "url".to_string();
+        let searx = info(json!({ "version": "1.1.0" }));
+        let searxng = info(json!({ "version": "2024.10.17+e7a2b6f" }));
+        let unknown = InstanceInfo::default();
+        let range = VersionRange::parse("2024.1.1", "").unwrap();
+        assert!(!filter_by_version((&url, &searx), Some(&range)));
+        assert!(filter_by_version((&url, &searxng), Some(&range)));
+        assert!(!filter_by_version((&url, &unknown), Some(&range)));
+        assert!(filter_by_version((&url, &unknown), None));
+
+        let filter = Filter {
+            versions: Some(("2024.1.1".to_string(), "1.0.0".to_string())),
+            ..Filter::default()
+        };
+        assert!(filter.version_range().is_err());
+    }
+
+    #[test]
+    fn filter_by_security_grades_test() {
+        let url = "url".to_string();
+        let instance = info(json!({
+            "tls": { "grade": "A+" },
+            "http": { "grade": "B" },
+        }));
+        assert!(filter_by_security_grades(
+            (&url, &instance),
+            &Filter::default()
+        ));
+
+        let filter = Filter {
+            min_tls_grade: Some("A".parse().unwrap()),
+            min_http_grade: Some("B-".parse().unwrap()),
+            ..Filter::default()
+        };
+        assert!(filter_by_security_grades((&url, &instance), &filter));
+
+        let filter = Filter {
+            min_http_grade: Some("A-".parse().unwrap()),
+            ..filter
+        };
+        assert!(!filter_by_security_grades((&url, &instance), &filter));
+
+        let filter = Filter {
+            min_csp_grade: Some("F".parse().unwrap()),
+            ..Filter::default()
+        };
+        assert!(!filter_by_security_grades((&url, &instance), &filter));
+    }
+
+    #[test]
+    fn filter_by_grade_test() {
+        let url = "url".to_string();
+        let instance = |grade: &str| info(json!({ "html": { "grade": grade } }));
+        let passes =
+            |grade: &str, filter: &Filter| filter_by_grade((&url, &instance(grade)), filter);
+
+        let filter = Filter::default();
+        assert!(passes("V", &filter) && passes("C", &filter));
+        assert!(!passes("Cjs", &filter) && !passes("E", &filter) && !passes("?", &filter));
+
+        let vanilla_only = Filter {
+            min_grade: Some(HtmlGrade::V),
+            ..Filter::default()
+        };
+        assert!(passes("V", &vanilla_only) && !passes("C", &vanilla_only));
+
+        let allow_js = Filter {
+            min_grade: Some(HtmlGrade::Cjs),
+            ..Filter::default()
+        };
+        assert!(passes("Cjs", &allow_js) && !passes("E", &allow_js));
+
+        let set = Filter {
+            grades: Some(vec!["E".to_string()]),
+            ..allow_js
+        };
+        assert!(passes("E", &set) && !passes("V", &set));
+    }
 }
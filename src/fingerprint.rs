@@ -0,0 +1,286 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use log::warn;
+use rand::{seq::SliceRandom, Rng};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HeaderProfile {
+    pub name: String,
+    /// Sent in this order, e.g. `[["User-Agent", "Mozilla/5.0 ..."], ["Accept", "..."]]`.
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    /// Every request picks a random profile.
+    PerRequest,
+    /// Every instance always sees the same profile.
+    PerInstance,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+    /// Empty means the built-in browser profiles.
+    pub profiles: Vec<HeaderProfile>,
+    pub rotation: Rotation,
+    /// Send the user's own `Accept-Language` instead of the profile one.
+    pub forward_accept_language: bool,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        Self {
+            profiles: Vec::new(),
+            rotation: Rotation::PerInstance,
+            forward_accept_language: false,
+        }
+    }
+}
+
+impl FingerprintConfig {
+    pub fn pick(&self, instance_url: &str, rng: &mut impl Rng) -> HeaderProfile {
+        let profiles = if self.profiles.is_empty() {
+            builtin_profiles()
+        } else {
+            self.profiles.clone()
+        };
+        match self.rotation {
+            Rotation::PerRequest => profiles.choose(rng).cloned().unwrap(),
+            Rotation::PerInstance => {
+                let mut hasher = DefaultHasher::new();
+                instance_url.hash(&mut hasher);
+                profiles[(hasher.finish() % profiles.len() as u64) as usize].clone()
+            }
+        }
+    }
+
+    /// Headers of `profile`, with `accept_language` replacing the profile's one when
+    /// forwarding is enabled. Headers that are not valid HTTP are skipped.
+    pub fn headers(&self, profile: &HeaderProfile, accept_language: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &profile.headers {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => warn!(
+                    "skipping invalid header {name:?} of profile {}",
+                    profile.name
+                ),
+            }
+        }
+        if self.forward_accept_language {
+            if let Some(value) = accept_language.and_then(|value| HeaderValue::from_str(value).ok())
+            {
+                headers.insert(ACCEPT_LANGUAGE, value);
+            }
+        }
+        headers
+    }
+}
+
+fn profile(name: &str, headers: &[(&str, &str)]) -> HeaderProfile {
+    HeaderProfile {
+        name: name.to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+pub fn builtin_profiles() -> Vec<HeaderProfile> {
+    let firefox_accept =
+        "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/png,image/svg+xml,*/*;q=0.8";
+    let chromium_accept = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+    vec![
+        profile(
+            "firefox-windows",
+            &[
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:131.0) Gecko/20100101 Firefox/131.0",
+                ),
+                ("Accept", firefox_accept),
+                ("Accept-Language", "en-US,en;q=0.5"),
+                ("Upgrade-Insecure-Requests", "1"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-User", "?1"),
+            ],
+        ),
+        profile(
+            "firefox-linux",
+            &[
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0",
+                ),
+                ("Accept", firefox_accept),
+                ("Accept-Language", "en-GB,en;q=0.5"),
+                ("Upgrade-Insecure-Requests", "1"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-User", "?1"),
+            ],
+        ),
+        profile(
+            "chrome-windows",
+            &[
+                ("sec-ch-ua", r#""Chromium";v="130", "Google Chrome";v="130", "Not?A_Brand";v="99""#),
+                ("sec-ch-ua-mobile", "?0"),
+                ("sec-ch-ua-platform", r#""Windows""#),
+                ("Upgrade-Insecure-Requests", "1"),
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36",
+                ),
+                ("Accept", chromium_accept),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-User", "?1"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Accept-Language", "en-US,en;q=0.9"),
+            ],
+        ),
+        profile(
+            "chrome-macos",
+            &[
+                ("sec-ch-ua", r#""Chromium";v="130", "Google Chrome";v="130", "Not?A_Brand";v="99""#),
+                ("sec-ch-ua-mobile", "?0"),
+                ("sec-ch-ua-platform", r#""macOS""#),
+                ("Upgrade-Insecure-Requests", "1"),
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36",
+                ),
+                ("Accept", chromium_accept),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-User", "?1"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Accept-Language", "en-US,en;q=0.9"),
+            ],
+        ),
+        profile(
+            "edge-windows",
+            &[
+                ("sec-ch-ua", r#""Chromium";v="130", "Microsoft Edge";v="130", "Not?A_Brand";v="99""#),
+                ("sec-ch-ua-mobile", "?0"),
+                ("sec-ch-ua-platform", r#""Windows""#),
+                ("Upgrade-Insecure-Requests", "1"),
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 Edg/130.0.0.0",
+                ),
+                ("Accept", chromium_accept),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-User", "?1"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Accept-Language", "en-US,en;q=0.9"),
+            ],
+        ),
+        profile(
+            "safari-macos",
+            &[
+                (
+                    "User-Agent",
+                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Safari/605.1.15",
+                ),
+                ("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                ("Sec-Fetch-Site", "none"),
+                ("Sec-Fetch-Mode", "navigate"),
+                ("Sec-Fetch-Dest", "document"),
+                ("Accept-Language", "en-US,en;q=0.9"),
+            ],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use reqwest::header::USER_AGENT;
+
+    use super::*;
+
+    #[test]
+    fn builtin_profiles_are_valid_test() {
+        let config = FingerprintConfig::default();
+        for profile in builtin_profiles() {
+            let headers = config.headers(&profile, None);
+            assert_eq!(headers.len(), profile.headers.len(), "{}", profile.name);
+            assert!(headers.contains_key(USER_AGENT));
+            assert!(headers.contains_key(ACCEPT_LANGUAGE));
+        }
+    }
+
+    #[test]
+    fn fingerprint_config_defaults_test() {
+        let config: FingerprintConfig =
+            serde_json::from_str(r#"{"forward_accept_language": true}"#).unwrap();
+        assert!(config.forward_accept_language);
+        assert_eq!(config.rotation, Rotation::PerInstance);
+    }
+
+    #[test]
+    fn pick_per_instance_is_stable_test() {
+        let config = FingerprintConfig::default();
+        let mut rng = StdRng::seed_from_u64(1);
+        let first = config.pick("https://searx.be/", &mut rng);
+        for _ in 0..10 {
+            assert_eq!(config.pick("https://searx.be/", &mut rng), first);
+        }
+    }
+
+    #[test]
+    fn pick_per_request_rotates_test() {
+        let config = FingerprintConfig {
+            rotation: Rotation::PerRequest,
+            ..FingerprintConfig::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let names: std::collections::HashSet<String> = (0..50)
+            .map(|_| config.pick("https://searx.be/", &mut rng).name)
+            .collect();
+        assert!(names.len() > 1);
+    }
+
+    #[test]
+    fn headers_test() {
+        let profile = profile(
+            "custom",
+            &[
+                ("User-Agent", "custom-agent"),
+                ("Accept-Language", "pl"),
+                ("Bad Header", "x"),
+            ],
+        );
+        let config = FingerprintConfig::default();
+        let headers = config.headers(&profile, Some("de-DE"));
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[ACCEPT_LANGUAGE], "pl");
+
+        let config = FingerprintConfig {
+            forward_accept_language: true,
+            ..config
+        };
+        let headers = config.headers(&profile, Some("de-DE"));
+        assert_eq!(headers[ACCEPT_LANGUAGE], "de-DE");
+        let headers = config.headers(&profile, None);
+        assert_eq!(headers[ACCEPT_LANGUAGE], "pl");
+    }
+}
@@ -62,8 +62,7 @@ impl Executor {
             release: None,
             fe_path: "./web/".to_string(),
         };
-        let manager = Box::new(manager);
-        Self { manager }
+        Self::new(Box::new(manager))
     }
     pub async fn init(&mut self) -> Result<()> {
         self.manager
@@ -116,6 +115,13 @@ impl FEManager for Manager {
         let release = releases
             .first()
             .ok_or_else(|| anyhow!("No release found"))?;
+        info!(
+            "Found release {} (version {}, created {})",
+            release.name, release.version, release.date
+        );
+        if let Some(body) = &release.body {
+            debug!("Release notes: {}", body);
+        }
         self.release = Some(release.clone());
         Ok(())
     }
@@ -135,7 +141,7 @@ impl FEManager for Manager {
         download.show_progress(true);
         let name = &self.get_release()?.get_first_asset()?.name;
         debug!("Downloading {} to {}", name, name);
-        let mut tmp_archive = fs::File::create(&name)
+        let mut tmp_archive = fs::File::create(name)
             .map_err(|err| anyhow!("Error during File::create. path {} Err {}", name, err))?;
         task::spawn_blocking(move || {
             download.download_to(&mut tmp_archive).unwrap();
@@ -150,7 +156,7 @@ impl FEManager for Manager {
     }
     fn unzip_release(&self) -> Result<()> {
         let name = &self.get_release()?.get_first_asset()?.name;
-        unzip(&name, &self.fe_path, None::<fn(&OsStr) -> bool>)?;
+        unzip(name, &self.fe_path, None::<fn(&OsStr) -> bool>)?;
         Ok(())
     }
     fn remove_fe_folder(&self) -> Result<()> {
@@ -65,7 +65,7 @@ where
             );
             if let Some(p) = final_path.parent() {
                 if !p.exists() {
-                    fs::create_dir_all(&p)?;
+                    fs::create_dir_all(p)?;
                 }
             }
             let mut outfile = fs::File::create(&final_path)?;
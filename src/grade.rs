@@ -0,0 +1,143 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// School grade as used by searx.space for TLS, CSP and HTTP headers, `A+` being the best
+/// and `F` the worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LetterGrade {
+    /// Higher is better.
+    score: i8,
+}
+
+const LETTERS: &str = "FEDCBA";
+
+/// searx.space `html.grade`: how far the instance's HTML and static files differ from
+/// the upstream release. Ordered from worst to best.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum HtmlGrade {
+    /// Loads resources from other sites.
+    E,
+    /// Modified, including the JavaScript.
+    Cjs,
+    /// Modified, but the JavaScript is untouched.
+    C,
+    /// Vanilla, unmodified upstream files.
+    V,
+}
+
+impl HtmlGrade {
+    /// Used when the filter sets neither a minimum nor a set of grades: modified
+    /// instances pass, client-side JavaScript changes don't.
+    pub const DEFAULT_MINIMUM: HtmlGrade = HtmlGrade::C;
+}
+
+impl FromStr for HtmlGrade {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim() {
+            "V" => Ok(HtmlGrade::V),
+            "C" => Ok(HtmlGrade::C),
+            "Cjs" => Ok(HtmlGrade::Cjs),
+            "E" => Ok(HtmlGrade::E),
+            other => Err(format!("invalid html grade {other:?}")),
+        }
+    }
+}
+
+impl FromStr for LetterGrade {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let mut chars = text.chars();
+        let letter = chars
+            .next()
+            .map(|letter| letter.to_ascii_uppercase())
+            .and_then(|letter| LETTERS.find(letter))
+            .ok_or_else(|| format!("invalid grade {text:?}"))?;
+        let modifier = match chars.as_str() {
+            "" => 0,
+            "+" => 1,
+            "-" => -1,
+            _ => return Err(format!("invalid grade {text:?}")),
+        };
+        Ok(Self {
+            score: letter as i8 * 3 + modifier,
+        })
+    }
+}
+
+impl TryFrom<String> for LetterGrade {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<LetterGrade> for String {
+    fn from(grade: LetterGrade) -> Self {
+        grade.to_string()
+    }
+}
+
+impl fmt::Display for LetterGrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = LETTERS.as_bytes()[(self.score + 1).div_euclid(3) as usize] as char;
+        let modifier = match (self.score + 1).rem_euclid(3) {
+            0 => "-",
+            2 => "+",
+            _ => "",
+        };
+        write!(f, "{letter}{modifier}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grade(text: &str) -> LetterGrade {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn letter_grade_order_test() {
+        assert!(grade("A+") > grade("A"));
+        assert!(grade("A") > grade("A-"));
+        assert!(grade("A-") > grade("B+"));
+        assert!(grade("B") > grade("C"));
+        assert!(grade("D-") > grade("F"));
+        assert_eq!(grade("a+"), grade("A+"));
+        assert!("G".parse::<LetterGrade>().is_err());
+        assert!("A++".parse::<LetterGrade>().is_err());
+        assert!("".parse::<LetterGrade>().is_err());
+    }
+
+    #[test]
+    fn html_grade_order_test() {
+        assert!(HtmlGrade::V > HtmlGrade::C);
+        assert!(HtmlGrade::C > HtmlGrade::Cjs);
+        assert!(HtmlGrade::Cjs > HtmlGrade::E);
+        assert_eq!("Cjs".parse(), Ok(HtmlGrade::Cjs));
+        assert!("cjs".parse::<HtmlGrade>().is_err());
+        assert_eq!(serde_json::to_string(&HtmlGrade::Cjs).unwrap(), r#""Cjs""#);
+    }
+
+    #[test]
+    fn letter_grade_round_trip_test() {
+        for text in ["A+", "A", "A-", "B+", "C", "F-", "F"] {
+            assert_eq!(grade(text).to_string(), text);
+        }
+        let json = serde_json::to_string(&grade("B+")).unwrap();
+        assert_eq!(json, r#""B+""#);
+        assert_eq!(
+            serde_json::from_str::<LetterGrade>(&json).unwrap(),
+            grade("B+")
+        );
+        assert!(serde_json::from_str::<LetterGrade>(r#""Z""#).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+pub mod api;
 pub mod save;
 pub mod search;
 pub mod search_helpers;
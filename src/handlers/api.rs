@@ -0,0 +1,194 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    health::InstanceStatus,
+    instance_info::{MalformedField, RejectedRecord},
+    searx_client::SearxProvider,
+    snapshot::{age_secs, unix_secs},
+    AppConfig, Cache,
+};
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, HttpResponse, Responder,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    search::Query,
+    search_helpers::{self, DEFAULT_ATTEMPT_TIMEOUT_MS, DEFAULT_MAX_ATTEMPTS, INSTANCES_HEADER},
+};
+
+#[derive(Deserialize)]
+pub struct FanOutQuery {
+    instances: Option<usize>,
+    deadline_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Status {
+    instances: usize,
+    /// The pool comes from the on-disk snapshot, the sources could not be fetched.
+    stale: bool,
+    /// Unix time in seconds of the fetch the pool is based on.
+    fetched_at: Option<u64>,
+    age_secs: Option<u64>,
+    /// Records of the last listing that could not be parsed and were left out.
+    rejected: Vec<RejectedRecord>,
+    /// Fields of the last listing that had the wrong type and were left unset.
+    malformed: Vec<MalformedField>,
+}
+
+pub async fn api_search(
+    req: HttpRequest,
+    params: web::Query<Query>,
+    cache: Data<Mutex<Cache>>,
+    app_config: Data<Mutex<AppConfig>>,
+    client: Data<Arc<dyn SearxProvider>>,
+) -> impl Responder {
+    match search_helpers::populate_cache_if_needed(&cache, &client, &app_config).await {
+        Ok(it) => it,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let (max_attempts, attempt_timeout, breaker, strategy, defaults) = {
+        let app_conf_guard = app_config.lock().unwrap();
+        (
+            app_conf_guard.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            Duration::from_millis(
+                app_conf_guard
+                    .attempt_timeout_ms
+                    .unwrap_or(DEFAULT_ATTEMPT_TIMEOUT_MS),
+            ),
+            app_conf_guard.breaker.clone().unwrap_or_default(),
+            app_conf_guard.strategy.unwrap_or_default(),
+            app_conf_guard.search_defaults.clone().unwrap_or_default(),
+        )
+    };
+    let query = match params.resolve(&defaults, &req) {
+        Ok(it) => it,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let (result, tried) = search_helpers::search_with_failover(
+        &cache,
+        max_attempts,
+        attempt_timeout,
+        &breaker,
+        params.strategy.unwrap_or(strategy),
+        params.instance.clone(),
+        |url| {
+            let client = client.get_ref().clone();
+            let query = query.clone();
+            async move { client.get_instance_search_json(&url, &query).await }
+        },
+    )
+    .await;
+    let tried_header = (INSTANCES_HEADER, tried.join(", "));
+    match result {
+        Ok(response) => HttpResponse::Ok()
+            .insert_header(tried_header)
+            .json(response),
+        Err(err) => HttpResponse::InternalServerError()
+            .insert_header(tried_header)
+            .body(err.to_string()),
+    }
+}
+
+pub async fn api_fan_out_search(
+    req: HttpRequest,
+    params: web::Query<Query>,
+    fan_out_params: web::Query<FanOutQuery>,
+    cache: Data<Mutex<Cache>>,
+    app_config: Data<Mutex<AppConfig>>,
+    client: Data<Arc<dyn SearxProvider>>,
+) -> impl Responder {
+    match search_helpers::populate_cache_if_needed(&cache, &client, &app_config).await {
+        Ok(it) => it,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let (fan_out_conf, breaker, strategy, defaults) = {
+        let app_conf_guard = app_config.lock().unwrap();
+        (
+            app_conf_guard.fan_out.clone().unwrap_or_default(),
+            app_conf_guard.breaker.clone().unwrap_or_default(),
+            app_conf_guard.strategy.unwrap_or_default(),
+            app_conf_guard.search_defaults.clone().unwrap_or_default(),
+        )
+    };
+    let query = match params.resolve(&defaults, &req) {
+        Ok(it) => it,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let instances = fan_out_params.instances.unwrap_or(fan_out_conf.instances);
+    let deadline_ms = fan_out_params
+        .deadline_ms
+        .unwrap_or(fan_out_conf.deadline_ms);
+    let urls = search_helpers::select_urls_from_cache(
+        &cache,
+        instances,
+        &breaker,
+        params.strategy.unwrap_or(strategy),
+    );
+    let (response, latencies) = search_helpers::fan_out_search(
+        client.get_ref().clone(),
+        urls,
+        query,
+        Duration::from_millis(deadline_ms),
+    )
+    .await;
+    for url in &response.instances {
+        search_helpers::record_success(&cache, url, latencies.get(url).copied());
+    }
+    for url in &response.failed_instances {
+        search_helpers::record_failure(
+            &cache,
+            url,
+            "fan-out request failed or missed the deadline".to_string(),
+            &breaker,
+        );
+    }
+    HttpResponse::Ok().json(response)
+}
+
+pub async fn api_instances(
+    cache: Data<Mutex<Cache>>,
+    app_config: Data<Mutex<AppConfig>>,
+) -> impl Responder {
+    let breaker = app_config
+        .lock()
+        .unwrap()
+        .breaker
+        .clone()
+        .unwrap_or_default();
+    let cache_guard = cache.lock().unwrap();
+    let statuses: Vec<InstanceStatus> = cache_guard
+        .instances
+        .iter()
+        .map(|url| {
+            let health = cache_guard.health.get(url).cloned().unwrap_or_default();
+            InstanceStatus {
+                url: url.clone(),
+                state: health.state(&breaker),
+                consecutive_failures: health.consecutive_failures,
+                last_error: health.last_error,
+                latency_ms: health.latency.map(|latency| latency.as_millis()),
+                success_rate: health.success_rate,
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(statuses)
+}
+
+pub async fn api_status(cache: Data<Mutex<Cache>>) -> impl Responder {
+    let cache_guard = cache.lock().unwrap();
+    HttpResponse::Ok().json(Status {
+        instances: cache_guard.instances.len(),
+        stale: cache_guard.stale,
+        fetched_at: cache_guard.fetched_at.map(unix_secs),
+        age_secs: cache_guard.fetched_at.map(age_secs),
+        rejected: cache_guard.rejected.clone(),
+        malformed: cache_guard.malformed.clone(),
+    })
+}
@@ -5,7 +5,8 @@ use std::{
 };
 
 use crate::{
-    filter::{get_filtered_urls, Filter, Timings},
+    filter::{AsnPrivacy, Filter, TimingCriteria, Timings, UptimeCriteria},
+    grade::{HtmlGrade, LetterGrade},
     searx_client::SearxProvider,
     AppConfig, Cache, CONFIG_FILENAME,
 };
@@ -19,15 +20,82 @@ use anyhow::{self, Result};
 use log::info;
 use serde::Deserialize;
 
+use super::search_helpers::refresh_with_filter;
+
 #[derive(Deserialize, Debug)]
 pub struct SaveDto {
-    search: Option<String>,
-    google: Option<String>,
-    wikipedia: Option<String>,
-    initial: Option<String>,
+    search: Option<TimingForm>,
+    #[serde(alias = "google")]
+    search_go: Option<TimingForm>,
+    #[serde(alias = "wikipedia")]
+    search_wp: Option<TimingForm>,
+    initial: Option<TimingForm>,
     grades: Option<Vec<String>>,
+    min_grade: Option<HtmlGrade>,
     min_version: Option<String>,
     max_version: Option<String>,
+    networks: Option<Vec<String>>,
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+    min_tls_grade: Option<String>,
+    min_csp_grade: Option<String>,
+    min_http_grade: Option<String>,
+    min_uptime: Option<UptimeCriteria>,
+    analytics: Option<bool>,
+    asn_privacy: Option<AsnPrivacy>,
+    ipv6: Option<bool>,
+    main: Option<bool>,
+}
+
+/// Thresholds of one timing bucket as entered, in seconds and percent. Text that is
+/// not a number sets no threshold.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum TimingForm {
+    /// Just the maximum mean, as sent by older forms.
+    Mean(String),
+    Criteria {
+        mean: Option<String>,
+        median: Option<String>,
+        stdev: Option<String>,
+        success: Option<String>,
+    },
+}
+
+impl TimingForm {
+    fn criteria(&self) -> TimingCriteria {
+        let number = |text: &Option<String>| text.as_deref().and_then(parse_number);
+        match self {
+            TimingForm::Mean(mean) => TimingCriteria {
+                max_mean: parse_number(mean),
+                ..TimingCriteria::default()
+            },
+            TimingForm::Criteria {
+                mean,
+                median,
+                stdev,
+                success,
+            } => TimingCriteria {
+                max_mean: number(mean),
+                max_median: number(median),
+                max_stdev: number(stdev),
+                min_success: number(success),
+            },
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse().ok()
+}
+
+/// Empty means no minimum.
+fn parse_grade(text: &Option<String>) -> Result<Option<LetterGrade>, String> {
+    text.as_deref()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::parse)
+        .transpose()
 }
 
 pub async fn save(
@@ -36,33 +104,52 @@ pub async fn save(
     client: Data<Arc<dyn SearxProvider>>,
     app_config: Data<Mutex<AppConfig>>,
 ) -> impl Responder {
-    let fetched_instances = match client.fetch_instances().await {
+    let parse_grades = || -> Result<_, String> {
+        Ok((
+            parse_grade(&body.min_tls_grade)?,
+            parse_grade(&body.min_csp_grade)?,
+            parse_grade(&body.min_http_grade)?,
+        ))
+    };
+    let (min_tls_grade, min_csp_grade, min_http_grade) = match parse_grades() {
         Ok(it) => it,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        Err(err) => return HttpResponse::BadRequest().body(err),
     };
-    info!("instanes len {}", fetched_instances.len());
     let filter = Filter {
         response_times: Some(Timings {
-            search: body.search.as_ref().and_then(|text| text.parse().ok()),
-            google: body.google.as_ref().and_then(|text| text.parse().ok()),
-            wikipedia: body.wikipedia.as_ref().and_then(|text| text.parse().ok()),
-            initial: body.initial.as_ref().and_then(|text| text.parse().ok()),
+            search: body.search.as_ref().map(TimingForm::criteria),
+            search_go: body.search_go.as_ref().map(TimingForm::criteria),
+            search_wp: body.search_wp.as_ref().map(TimingForm::criteria),
+            initial: body.initial.as_ref().map(TimingForm::criteria),
         }),
-        grades: body.grades.clone(),
-        ..Filter::default()
+        grades: body.grades.clone().filter(|grades| !grades.is_empty()),
+        min_grade: body.min_grade,
+        versions: match (&body.min_version, &body.max_version) {
+            (None, None) => None,
+            (min, max) => Some((
+                min.clone().unwrap_or_default(),
+                max.clone().unwrap_or_default(),
+            )),
+        },
+        networks: body.networks.clone(),
+        allow: body.allow.clone(),
+        deny: body.deny.clone(),
+        min_tls_grade,
+        min_csp_grade,
+        min_http_grade,
+        min_uptime: body.min_uptime.clone(),
+        analytics: body.analytics,
+        asn_privacy: body.asn_privacy,
+        ipv6: body.ipv6,
+        main: body.main,
     };
-    let best_grade_instance_urls = get_filtered_urls(&fetched_instances, &filter);
-    info!("best grades len {}", best_grade_instance_urls.len());
-    let mut cache_guard = cache.lock().unwrap();
-    cache_guard.instances = best_grade_instance_urls
-        .iter()
-        .map(|url| url.to_string())
-        .collect();
-    drop(cache_guard);
-    let mut app_conf_guard = app_config.lock().unwrap();
-    app_conf_guard.filter = Some(filter);
-    let app_conf = app_conf_guard.clone();
-    drop(app_conf_guard);
+    if let Err(err) = filter.version_range() {
+        return HttpResponse::BadRequest().body(err);
+    }
+    if let Err(err) = refresh_with_filter(&cache, &client, &app_config, filter).await {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+    let app_conf = app_config.lock().unwrap().clone();
     info!("app_conf {app_conf:?}");
 
     let save_to_file = || -> Result<_, anyhow::Error> {
@@ -1,21 +1,93 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::{searx_client::SearxProvider, AppConfig, Cache};
+use crate::{
+    rewriter::HtmlRewriter,
+    search_params::{SearchDefaults, SearchParams},
+    searx_client::{BodyStream, SearxProvider},
+    selection::Strategy,
+    AppConfig, Cache,
+};
 use actix_web::{
+    http::header,
     web::{self, Data},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
 
 use serde::Deserialize;
 
-use super::search_helpers;
+use super::search_helpers::{
+    self, DEFAULT_ATTEMPT_TIMEOUT_MS, DEFAULT_MAX_ATTEMPTS, INSTANCES_HEADER,
+};
 
 #[derive(Deserialize)]
 pub struct Query {
     q: Option<String>,
+    pageno: Option<u32>,
+    categories: Option<String>,
+    language: Option<String>,
+    time_range: Option<String>,
+    safesearch: Option<u8>,
+    engines: Option<String>,
+    format: Option<String>,
+    /// Instance from the pool to try first.
+    pub(crate) instance: Option<String>,
+    /// Keep follow-up searches from the returned page on the same instance.
+    sticky: Option<bool>,
+    /// Overrides the configured instance selection strategy.
+    pub(crate) strategy: Option<Strategy>,
+}
+
+impl Query {
+    /// Fills the gaps with the configured defaults and validates the result.
+    pub fn resolve(
+        &self,
+        defaults: &SearchDefaults,
+        req: &HttpRequest,
+    ) -> Result<SearchParams, String> {
+        let params = SearchParams {
+            pageno: self.pageno,
+            categories: self.categories.clone(),
+            language: self.language.clone(),
+            time_range: self.time_range.clone(),
+            safesearch: self.safesearch,
+            engines: self.engines.clone(),
+            format: self.format.clone(),
+            accept_language: req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            ..SearchParams::new(self.q.as_deref().unwrap_or_default())
+        }
+        .with_defaults(defaults);
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// SearXNG category tabs submit `category_<name>=1` instead of `categories`.
+fn tab_categories(query_string: &str) -> Option<String> {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query_string).ok()?;
+    let categories: Vec<String> = pairs
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix("category_")
+                .filter(|_| value != "0" && value != "off")
+                .map(|category| category.to_string())
+        })
+        .collect();
+    if categories.is_empty() {
+        None
+    } else {
+        Some(categories.join(","))
+    }
 }
 
 pub async fn search(
+    req: HttpRequest,
     params: web::Query<Query>,
     cache: Data<Mutex<Cache>>,
     app_config: Data<Mutex<AppConfig>>,
@@ -25,13 +97,63 @@ pub async fn search(
         Ok(it) => it,
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
-    let url = search_helpers::get_random_url_from_cache(&cache);
-    let body = match client
-        .get_instance_search_body(&url, &params.q.clone().unwrap_or_default())
-        .await
-    {
+    let (max_attempts, attempt_timeout, breaker, strategy, defaults, rewrite_conf) = {
+        let app_conf_guard = app_config.lock().unwrap();
+        (
+            app_conf_guard.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            Duration::from_millis(
+                app_conf_guard
+                    .attempt_timeout_ms
+                    .unwrap_or(DEFAULT_ATTEMPT_TIMEOUT_MS),
+            ),
+            app_conf_guard.breaker.clone().unwrap_or_default(),
+            app_conf_guard.strategy.unwrap_or_default(),
+            app_conf_guard.search_defaults.clone().unwrap_or_default(),
+            app_conf_guard.rewrite.clone().unwrap_or_default(),
+        )
+    };
+    let mut params = params.into_inner();
+    params.categories = params
+        .categories
+        .or_else(|| tab_categories(req.query_string()));
+    let query = match params.resolve(&defaults, &req) {
         Ok(it) => it,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        Err(err) => return HttpResponse::BadRequest().body(err),
     };
-    HttpResponse::Ok().body(body)
+    let (result, tried) = search_helpers::search_with_failover(
+        &cache,
+        max_attempts,
+        attempt_timeout,
+        &breaker,
+        params.strategy.unwrap_or(strategy),
+        params.instance.clone(),
+        |url| {
+            let client = client.get_ref().clone();
+            let query = query.clone();
+            async move { client.get_instance_search_stream(&url, &query).await }
+        },
+    )
+    .await;
+    let tried_header = (INSTANCES_HEADER, tried.join(", "));
+    let body = result.and_then(|body| match tried.last() {
+        Some(instance_url) if query.is_html() => {
+            let sticky_instance = params
+                .sticky
+                .unwrap_or_default()
+                .then(|| instance_url.clone());
+            let rewritten = HtmlRewriter::new(instance_url, rewrite_conf)?
+                .sticky_to(sticky_instance)
+                .rewrite_stream(body);
+            Ok(Box::pin(rewritten) as BodyStream)
+        }
+        _ => Ok(body),
+    });
+    match body {
+        Ok(body) => HttpResponse::Ok()
+            .insert_header(tried_header)
+            .streaming(body),
+        Err(err) => HttpResponse::InternalServerError()
+            .insert_header(tried_header)
+            .body(err.to_string()),
+    }
 }
@@ -1,130 +1,523 @@
-use anyhow::Ok;
-use rand::{self, thread_rng, Rng};
+use anyhow::{anyhow, Ok};
+use rand::{self, thread_rng};
 
-use crate::{filter::get_filtered_urls, AppConfig};
+use crate::{
+    filter::{get_filtered_urls, Filter},
+    health::{healthy_candidates, BreakerConfig, BreakerState},
+    instance_info::{parse_instances, ParsedListing},
+    instance_source::{Fetched, InstanceList},
+    merge::{merge_responses, MergedSearchResponse},
+    search_params::SearchParams,
+    selection::{PublishedStats, SelectionContext, Strategy},
+    snapshot::{age_secs, Snapshot},
+    AppConfig, Instant,
+};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use crate::searx_client::SearxProvider;
 
 use crate::Cache;
 
-use std::sync::{Arc, Mutex};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
+use actix_rt::time::{timeout, Instant as DeadlineInstant};
 use actix_web::web::Data;
 
-pub fn get_random_url_from_cache(cache: &Data<Mutex<Cache>>) -> String {
-    let cache_guard = cache.lock().unwrap();
-    let instance_urls = &cache_guard.instances;
-    get_random_instance_url(instance_urls)
+pub static INSTANCES_HEADER: &str = "X-Rsearx-Instances";
+pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+pub const DEFAULT_ATTEMPT_TIMEOUT_MS: u64 = 10_000;
+/// How soon a stale pool retries the sources.
+pub const STALE_RETRY: Duration = Duration::from_secs(5 * 60);
+
+/// Picks an instance from the healthy part of the pool with `strategy`.
+pub fn select_url_from_cache(
+    cache: &Data<Mutex<Cache>>,
+    excluded: &[String],
+    breaker: &BreakerConfig,
+    strategy: Strategy,
+) -> Option<String> {
+    let mut cache_guard = cache.lock().unwrap();
+    let Cache {
+        instances,
+        health,
+        published,
+        selection,
+        ..
+    } = &mut *cache_guard;
+    let mut rng = thread_rng();
+    let candidates = healthy_candidates(instances, health, excluded, breaker, &mut rng);
+    if candidates.is_empty() {
+        return None;
+    }
+    let mut context = SelectionContext {
+        published,
+        health,
+        state: selection,
+    };
+    let url = candidates[strategy.build().select(&candidates, &mut context, &mut rng)].clone();
+    selection.mark_used(&url);
+    Some(url)
+}
+
+pub fn record_success(cache: &Data<Mutex<Cache>>, url: &str, latency: Option<Duration>) {
+    let mut cache_guard = cache.lock().unwrap();
+    cache_guard
+        .health
+        .entry(url.to_string())
+        .or_default()
+        .record_success(latency);
+}
+
+pub fn record_failure(
+    cache: &Data<Mutex<Cache>>,
+    url: &str,
+    error: String,
+    breaker: &BreakerConfig,
+) {
+    let mut cache_guard = cache.lock().unwrap();
+    cache_guard
+        .health
+        .entry(url.to_string())
+        .or_default()
+        .record_failure(error, breaker);
 }
 
+/// Runs `search` against instances picked by `strategy` until one succeeds, `max_attempts`
+/// is reached or the pool runs out. Any failure moves on to the next instance, be it an
+/// outage, an attempt taking longer than `attempt_timeout` or a refusal such as a 429.
+/// Instances that already failed are not picked again.
+/// A `preferred` instance from the pool is tried first, unless its breaker is open.
+/// Returns the result together with every instance that was tried, in order.
+pub(crate) async fn search_with_failover<T, F, Fut>(
+    cache: &Data<Mutex<Cache>>,
+    max_attempts: usize,
+    attempt_timeout: Duration,
+    breaker: &BreakerConfig,
+    strategy: Strategy,
+    preferred: Option<String>,
+    mut search: F,
+) -> (anyhow::Result<T>, Vec<String>)
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut tried: Vec<String> = Vec::new();
+    let mut last_error = anyhow!("no healthy instances available");
+    let mut preferred = preferred.filter(|url| {
+        let cache_guard = cache.lock().unwrap();
+        cache_guard.instances.contains(url)
+            && cache_guard
+                .health
+                .get(url)
+                .is_none_or(|health| health.state(breaker) != BreakerState::Open)
+    });
+    while tried.len() < max_attempts.max(1) {
+        let url = match preferred
+            .take()
+            .or_else(|| select_url_from_cache(cache, &tried, breaker, strategy))
+        {
+            Some(url) => url,
+            None => break,
+        };
+        tried.push(url.clone());
+        let started = Instant::now();
+        let result = timeout(attempt_timeout, search(url.clone()))
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow!(
+                    "no answer within {}ms",
+                    attempt_timeout.as_millis()
+                ))
+            });
+        match result {
+            Result::Ok(it) => {
+                record_success(cache, &url, Some(started.elapsed()));
+                return (Result::Ok(it), tried);
+            }
+            Err(err) => {
+                warn!("instance {url} failed: {err}");
+                record_failure(cache, &url, err.to_string(), breaker);
+                last_error = err;
+            }
+        }
+    }
+    (Err(last_error), tried)
+}
+
+/// Up to `count` distinct instances, picked one after another with `strategy`.
+pub fn select_urls_from_cache(
+    cache: &Data<Mutex<Cache>>,
+    count: usize,
+    breaker: &BreakerConfig,
+    strategy: Strategy,
+) -> Vec<String> {
+    let mut urls = Vec::new();
+    while urls.len() < count {
+        match select_url_from_cache(cache, &urls, breaker, strategy) {
+            Some(url) => urls.push(url),
+            None => break,
+        }
+    }
+    urls
+}
+
+/// Queries all `instance_urls` concurrently and merges whatever arrived before `deadline`.
+/// Instances that error out or miss the deadline are reported in `failed_instances`.
+/// Also returns the response time of every instance that answered.
+pub(crate) async fn fan_out_search(
+    client: Arc<dyn SearxProvider>,
+    instance_urls: Vec<String>,
+    query: SearchParams,
+    deadline: Duration,
+) -> (MergedSearchResponse, HashMap<String, Duration>) {
+    let deadline = DeadlineInstant::now() + deadline;
+    let handles: Vec<_> = instance_urls
+        .iter()
+        .map(|url| {
+            let client = client.clone();
+            let url = url.clone();
+            let query = query.clone();
+            actix_rt::spawn(async move {
+                let started = Instant::now();
+                client
+                    .get_instance_search_json(&url, &query)
+                    .await
+                    .map(|response| (response, started.elapsed()))
+            })
+        })
+        .collect();
+    let mut responses = Vec::new();
+    let mut latencies = HashMap::new();
+    let mut failed_instances = Vec::new();
+    for (url, mut handle) in instance_urls.into_iter().zip(handles) {
+        let remaining = deadline.saturating_duration_since(DeadlineInstant::now());
+        match timeout(remaining, &mut handle).await {
+            Result::Ok(Result::Ok(Result::Ok((response, latency)))) => {
+                latencies.insert(url, latency);
+                responses.push(response);
+            }
+            Result::Ok(Result::Ok(Err(err))) => {
+                warn!("fan-out instance {url} failed: {err}");
+                failed_instances.push(url);
+            }
+            Result::Ok(Err(err)) => {
+                warn!("fan-out task for {url} failed: {err}");
+                failed_instances.push(url);
+            }
+            Err(_) => {
+                warn!("fan-out instance {url} missed the deadline");
+                handle.abort();
+                failed_instances.push(url);
+            }
+        }
+    }
+    let merged = MergedSearchResponse {
+        query: query.q,
+        instances: responses
+            .iter()
+            .map(|response| response.instance.clone())
+            .collect(),
+        failed_instances,
+        results: merge_responses(&responses),
+    };
+    (merged, latencies)
+}
+
+/// Only a cold, empty pool makes the request wait for the sources, refreshing
+/// an expired pool is left to the background refresher.
 pub(crate) async fn populate_cache_if_needed(
     cache: &Data<Mutex<Cache>>,
     client: &Data<Arc<dyn SearxProvider>>,
     app_config: &Data<Mutex<AppConfig>>,
 ) -> anyhow::Result<()> {
-    let should_fetch;
-    {
+    let is_empty = cache.lock().unwrap().instances.is_empty();
+    if is_empty {
+        refresh_instances(cache, client, app_config).await?;
+    }
+    Ok(())
+}
+
+/// Fetches the instance list and replaces the pool. Concurrent calls are coalesced:
+/// only one fetch is in flight and the callers waiting for it share its outcome.
+pub(crate) async fn refresh_instances(
+    cache: &Data<Mutex<Cache>>,
+    client: &Data<Arc<dyn SearxProvider>>,
+    app_config: &Data<Mutex<AppConfig>>,
+) -> anyhow::Result<()> {
+    let (gate, seen_refreshes) = {
         let cache_guard = cache.lock().unwrap();
-        should_fetch = cache_guard.instances.is_empty() || ttl_exceeded(&cache_guard as &Cache);
-    }
-    // drop(instances_guard); clippy has some issues with drop so using bracket instead { }
-    if should_fetch {
-        let fetched_instances = client.fetch_instances().await?;
-        info!("instanes len {}", fetched_instances.len());
-        let app_conf_guard = app_config.lock().unwrap();
-        let filter = app_conf_guard.filter.clone().unwrap_or_default();
-        info!("filter: {filter:?}");
-        let best_grade_instance_urls = get_filtered_urls(&fetched_instances, &filter);
-
-        info!("best grades len {}", best_grade_instance_urls.len());
-        let mut cache_guard = cache.lock().unwrap();
-        cache_guard.instances = best_grade_instance_urls
-            .iter()
-            .map(|url| url.to_string())
-            .collect::<Vec<String>>();
+        (cache_guard.refresh_gate.clone(), cache_guard.refreshes)
+    };
+    let mut last_error = gate.lock().await;
+    let refreshed_meanwhile = cache.lock().unwrap().refreshes != seen_refreshes;
+    if refreshed_meanwhile {
+        debug!("sharing the result of a concurrent refresh");
+        return match &*last_error {
+            Some(err) => Err(anyhow!(err.clone())),
+            None => Ok(()),
+        };
+    }
+    let result = fetch_and_store_instances(cache, client, app_config).await;
+    *last_error = result.as_ref().err().map(|err| err.to_string());
+    cache.lock().unwrap().refreshes += 1;
+    result
+}
+
+/// Makes `filter` the configured filter and replaces the pool with the instances of the
+/// full listing passing it. Waits for any refresh already at the gate and never shares its
+/// outcome, as that refresh used the previous filter. On failure nothing changes.
+pub(crate) async fn refresh_with_filter(
+    cache: &Data<Mutex<Cache>>,
+    client: &Data<Arc<dyn SearxProvider>>,
+    app_config: &Data<Mutex<AppConfig>>,
+    filter: Filter,
+) -> anyhow::Result<()> {
+    let gate = cache.lock().unwrap().refresh_gate.clone();
+    let mut last_error = gate.lock().await;
+    // The new filter needs the full listing, so this fetch is never conditional.
+    let fetched = match client.fetch_instances(&HashMap::new()).await? {
+        Fetched::Modified(fetched) => fetched,
+        Fetched::NotModified => return Err(anyhow!("no instances were fetched")),
+    };
+    info!("instanes len {}", fetched.instances.len());
+    let filter = {
+        let mut app_conf_guard = app_config.lock().unwrap();
+        app_conf_guard.filter = Some(filter);
+        reachable_filter(&app_conf_guard)
+    };
+    store_instances(&mut cache.lock().unwrap(), &fetched.instances, &filter);
+    record_fetch(cache, fetched).await;
+    *last_error = None;
+    cache.lock().unwrap().refreshes += 1;
+    Ok(())
+}
+
+/// On failure the previous pool, or the snapshot when there is none, is kept as
+/// stale and retried after `STALE_RETRY`.
+async fn fetch_and_store_instances(
+    cache: &Data<Mutex<Cache>>,
+    client: &Data<Arc<dyn SearxProvider>>,
+    app_config: &Data<Mutex<AppConfig>>,
+) -> anyhow::Result<()> {
+    let validators = {
+        let cache_guard = cache.lock().unwrap();
+        // an empty pool can't be extended by a 304
+        if cache_guard.instances.is_empty() {
+            HashMap::new()
+        } else {
+            cache_guard.validators.clone()
+        }
+    };
+    let filter = reachable_filter(&app_config.lock().unwrap());
+    info!("filter: {filter:?}");
+    match client.fetch_instances(&validators).await {
+        Result::Ok(Fetched::Modified(fetched)) => {
+            info!("instanes len {}", fetched.instances.len());
+            store_instances(&mut cache.lock().unwrap(), &fetched.instances, &filter);
+            record_fetch(cache, fetched).await;
+        }
+        Result::Ok(Fetched::NotModified) => {
+            let mut cache_guard = cache.lock().unwrap();
+            cache_guard.creation_time = Instant::now();
+            cache_guard.stale = false;
+            cache_guard.fetched_at = Some(SystemTime::now());
+        }
+        Err(err) => {
+            let is_empty = cache.lock().unwrap().instances.is_empty();
+            if is_empty {
+                if let Err(snapshot_err) = load_snapshot(cache, &filter, STALE_RETRY).await {
+                    warn!("no instance snapshot to fall back to: {snapshot_err}");
+                    schedule_retry(&mut cache.lock().unwrap(), STALE_RETRY);
+                    return Err(err);
+                }
+            }
+            warn!("fetching instances failed, serving a stale list: {err}");
+            mark_stale(&mut cache.lock().unwrap(), STALE_RETRY);
+        }
     }
     Ok(())
 }
 
-pub(crate) fn get_random_instance_url(best_grade_instance_urls: &Vec<String>) -> String {
-    let mut rng = thread_rng();
-    let random_plus: u32 = rng.gen_range(0..best_grade_instance_urls.len() as u32);
-    let url = best_grade_instance_urls
-        .get(random_plus as usize)
+/// Filter from the config, limited to the networks the proxy setup can reach.
+pub(crate) fn reachable_filter(app_config: &AppConfig) -> Filter {
+    let mut filter = app_config.filter.clone().unwrap_or_default();
+    let proxy = app_config.proxy.clone().unwrap_or_default();
+    filter.networks = Some(proxy.reachable(filter.networks()));
+    filter
+}
+
+/// Replaces the pool with the instances passing `filter` and forgets the health of dropped ones.
+/// Records that don't parse are kept in `rejected`, fields of the wrong type in `malformed`.
+pub(crate) fn store_instances(cache: &mut Cache, fetched: &Map<String, Value>, filter: &Filter) {
+    let ParsedListing {
+        instances: parsed,
+        rejected,
+        malformed,
+    } = parse_instances(fetched);
+    if !rejected.is_empty() {
+        warn!("{} instance records could not be parsed", rejected.len());
+    }
+    cache.rejected = rejected;
+    cache.malformed = malformed;
+    let best_grade_instance_urls = get_filtered_urls(&parsed, filter);
+    info!("best grades len {}", best_grade_instance_urls.len());
+    cache.instances = best_grade_instance_urls
+        .iter()
+        .map(|url| url.to_string())
+        .collect();
+    cache.published = cache
+        .instances
+        .iter()
+        .map(|url| (url.clone(), PublishedStats::from_info(&parsed[url])))
+        .collect();
+    let Cache {
+        instances,
+        health,
+        probed_at,
+        ..
+    } = cache;
+    health.retain(|url, _| instances.contains(url));
+    probed_at.retain(|url, _| instances.contains(url));
+}
+
+/// Marks the pool fresh and writes the fetched list to the snapshot file, if one is configured.
+pub(crate) async fn record_fetch(cache: &Data<Mutex<Cache>>, fetched: InstanceList) {
+    let snapshot_path = {
+        let mut cache_guard = cache.lock().unwrap();
+        cache_guard.creation_time = Instant::now();
+        cache_guard.stale = false;
+        cache_guard.fetched_at = Some(SystemTime::now());
+        cache_guard.validators = fetched.validators.clone();
+        cache_guard.snapshot_path.clone()
+    };
+    if let Some(path) = snapshot_path {
+        if let Err(err) = Snapshot::new(fetched).save(&path).await {
+            warn!("could not write snapshot {}: {err}", path.display());
+        }
+    }
+}
+
+/// Fills the pool from the snapshot file and marks it stale. The cache isn't locked
+/// while the file is read.
+pub(crate) async fn load_snapshot(
+    cache: &Data<Mutex<Cache>>,
+    filter: &Filter,
+    retry_in: Duration,
+) -> anyhow::Result<()> {
+    let path = cache
+        .lock()
         .unwrap()
-        .to_string();
-    url
+        .snapshot_path
+        .clone()
+        .ok_or_else(|| anyhow!("no snapshot file configured"))?;
+    let snapshot = Snapshot::load(&path).await?;
+    let fetched_at = snapshot.fetched_at();
+    info!(
+        "loaded snapshot of {} instances from {}, {}s old",
+        snapshot.instances.len(),
+        path.display(),
+        age_secs(fetched_at)
+    );
+    let mut cache_guard = cache.lock().unwrap();
+    store_instances(&mut cache_guard, &snapshot.instances, filter);
+    cache_guard.fetched_at = Some(fetched_at);
+    cache_guard.validators = snapshot.validators;
+    mark_stale(&mut cache_guard, retry_in);
+    Ok(())
+}
+
+fn mark_stale(cache: &mut Cache, retry_in: Duration) {
+    cache.stale = true;
+    schedule_retry(cache, retry_in);
+}
+
+/// The next fetch is attempted `retry_in` from now instead of a whole ttl later.
+fn schedule_retry(cache: &mut Cache, retry_in: Duration) {
+    let now = Instant::now();
+    cache.creation_time = now
+        .checked_sub(cache.ttl.saturating_sub(retry_in))
+        .unwrap_or(now);
 }
 
 pub(crate) fn ttl_exceeded(cache: &Cache) -> bool {
-    let elapsed = cache.creation_time.elapsed().as_secs();
+    let elapsed = cache.creation_time.elapsed();
     debug!("elapsed {elapsed:?}");
-    elapsed > cache.ttl.as_secs()
+    elapsed >= cache.ttl
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use serde_json::{json, Map, Value};
+    use serde_json::json;
 
     use super::*;
-    use crate::{searx_client::MockSearxProvider, HOUR};
+    use crate::{
+        instance_source::Validators,
+        search_results::{SearchResponse, SearchResult},
+        searx_client::{tests::status_error, MockSearxProvider},
+        HOUR,
+    };
     use core::time::Duration;
+    use futures_util::FutureExt;
     use mock_instant::{Instant, MockClock};
 
-    fn fetch_instances_return_mock() -> Box<dyn Fn() -> anyhow::Result<Map<String, Value>> + Send> {
-        let default = || {
-            let mut m = Map::new();
-            let json = json!({
-            "html": {
-                "grade": "C",
+    fn instance_list() -> InstanceList {
+        let mut m = Map::new();
+        let json = json!({
+        "html": {
+            "grade": "C",
+        },
+        "network_type": "normal"
+        });
+        m.insert("instance".to_string(), json);
+        let mut validators = HashMap::new();
+        validators.insert(
+            "https://searx.space/".to_string(),
+            Validators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: None,
             },
-            "network_type": "normal"
-            });
-            m.insert("instance".to_string(), json);
-            Ok(m)
-        };
-        Box::new(default)
+        );
+        InstanceList {
+            instances: m,
+            validators,
+        }
+    }
+
+    type FetchInstancesMock =
+        Box<dyn Fn(&HashMap<String, Validators>) -> anyhow::Result<Fetched<InstanceList>> + Send>;
+
+    fn fetch_instances_return_mock() -> FetchInstancesMock {
+        Box::new(|_| Ok(Fetched::Modified(instance_list())))
     }
 
     #[test]
     fn ttl_exceeded_test() {
         let creation_time = Instant::now();
-        let cache = Cache {
-            creation_time,
-            instances: Vec::new(),
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+        let cache = Cache::new(Vec::new(), creation_time, Duration::from_secs(HOUR.into()));
         assert!(!ttl_exceeded(&cache));
 
         let creation_time = Instant::now();
-        let cache = Cache {
-            creation_time,
-            instances: Vec::new(),
-            ttl: Duration::from_secs(25),
-        };
+        let cache = Cache::new(Vec::new(), creation_time, Duration::from_secs(25));
         assert!(!ttl_exceeded(&cache));
 
         MockClock::advance(Duration::from_secs(5));
-        let cache = Cache {
-            creation_time,
-            instances: Vec::new(),
-            ttl: Duration::from_secs(2),
-        };
+        let cache = Cache::new(Vec::new(), creation_time, Duration::from_secs(2));
         assert!(ttl_exceeded(&cache));
 
         MockClock::advance(Duration::from_secs(HOUR.into()));
-        let cache = Cache {
-            creation_time,
-            instances: Vec::new(),
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+        let cache = Cache::new(Vec::new(), creation_time, Duration::from_secs(HOUR.into()));
         assert!(ttl_exceeded(&cache));
     }
 
     #[actix_rt::test]
-    async fn populate_cache_if_needed_fetch_instances_be_called_test() {
+    async fn refresh_instances_replaces_pool_test() {
         let mut client_mock = MockSearxProvider::new();
         client_mock
             .expect_fetch_instances()
@@ -133,21 +526,170 @@ pub(crate) mod tests {
         let creation_time = Instant::now();
         MockClock::advance(Duration::from_secs(10));
         MockClock::advance(Duration::from_secs(HOUR.into()));
-        let cache = Cache {
-            instances: vec!["1".to_string()],
+        let cache = Cache::new(
+            vec!["1".to_string()],
             creation_time,
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+            Duration::from_secs(HOUR.into()),
+        );
         let cache = Data::new(Mutex::new(cache));
         let client_mock = Arc::new(client_mock) as Arc<dyn SearxProvider>;
         let client_mock = Data::new(client_mock);
         let app_conf = Data::new(Mutex::new(AppConfig::default()));
-        populate_cache_if_needed(&cache, &client_mock, &app_conf).await;
+        refresh_instances(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
         let cache_guard = cache.lock().unwrap();
         let instances = &cache_guard.instances;
 
         assert_eq!(instances.len(), 1);
         assert_eq!(instances[0], "instance".to_string());
+        assert!(!ttl_exceeded(&cache_guard));
+    }
+
+    #[actix_rt::test]
+    async fn populate_cache_if_needed_coalesces_concurrent_refreshes_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .times(1)
+            .returning(fetch_instances_return_mock());
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        let cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
+        let gate = cache.refresh_gate.clone();
+        let cache = Data::new(Mutex::new(cache));
+
+        let held_gate = gate.lock().await;
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, client_mock, app_conf) =
+                    (cache.clone(), client_mock.clone(), app_conf.clone());
+                actix_rt::spawn(async move {
+                    populate_cache_if_needed(&cache, &client_mock, &app_conf).await
+                })
+            })
+            .collect();
+        // let every request reach the gate before the first fetch can start
+        actix_rt::task::yield_now().await;
+        drop(held_gate);
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        assert_eq!(
+            cache.lock().unwrap().instances,
+            vec!["instance".to_string()]
+        );
+        assert_eq!(cache.lock().unwrap().refreshes, 1);
+    }
+
+    #[actix_rt::test]
+    async fn refresh_instances_shares_failure_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .times(1)
+            .returning(|_| Err(anyhow!("searx.space unreachable")));
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        let cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
+        let gate = cache.refresh_gate.clone();
+        let cache = Data::new(Mutex::new(cache));
+
+        let held_gate = gate.lock().await;
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let (cache, client_mock, app_conf) =
+                    (cache.clone(), client_mock.clone(), app_conf.clone());
+                actix_rt::spawn(
+                    async move { refresh_instances(&cache, &client_mock, &app_conf).await },
+                )
+            })
+            .collect();
+        actix_rt::task::yield_now().await;
+        drop(held_gate);
+        for handle in handles {
+            let err = handle.await.unwrap().unwrap_err();
+            assert_eq!(err.to_string(), "searx.space unreachable");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn refresh_with_filter_runs_after_pending_refresh_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .times(1)
+            .withf(|validators| validators.is_empty())
+            .returning(fetch_instances_return_mock());
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        let mut cache = Cache::new(
+            vec!["1".to_string()],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
+        cache
+            .validators
+            .insert("public".to_string(), Validators::default());
+        let gate = cache.refresh_gate.clone();
+        let cache = Data::new(Mutex::new(cache));
+        let filter = Filter {
+            allow: Some(vec!["instance".to_string()]),
+            ..Filter::default()
+        };
+
+        let held_gate = gate.lock().await;
+        let mut save = Box::pin(refresh_with_filter(&cache, &client_mock, &app_conf, filter));
+        assert!(save.as_mut().now_or_never().is_none());
+        // a refresh with the previous filter completes while the save waits
+        cache.lock().unwrap().refreshes += 1;
+        drop(held_gate);
+        save.await.unwrap();
+        assert_eq!(
+            cache.lock().unwrap().instances,
+            vec!["instance".to_string()]
+        );
+        assert_eq!(cache.lock().unwrap().refreshes, 2);
+        let app_conf_guard = app_conf.lock().unwrap();
+        assert!(app_conf_guard.filter.as_ref().unwrap().allow.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn refresh_with_filter_keeps_filter_on_failure_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .returning(|_| Err(anyhow!("searx.space unreachable")));
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        let cache = cache_with(&["1"]);
+        assert!(
+            refresh_with_filter(&cache, &client_mock, &app_conf, Filter::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(cache.lock().unwrap().instances, vec!["1".to_string()]);
+        assert!(app_conf.lock().unwrap().filter.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn populate_cache_if_needed_serves_expired_pool_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock.expect_fetch_instances().never();
+        let creation_time = Instant::now();
+        MockClock::advance(Duration::from_secs(HOUR.into()) + Duration::from_secs(10));
+        let cache = Cache::new(
+            vec!["1".to_string()],
+            creation_time,
+            Duration::from_secs(HOUR.into()),
+        );
+        let cache = Data::new(Mutex::new(cache));
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        populate_cache_if_needed(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
+        assert_eq!(cache.lock().unwrap().instances, vec!["1".to_string()]);
     }
 
     #[actix_rt::test]
@@ -157,16 +699,14 @@ pub(crate) mod tests {
             .expect_fetch_instances()
             .return_once(fetch_instances_return_mock());
 
-        let cache = Cache {
-            instances: Vec::new(),
-            creation_time: Instant::now(),
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+        let cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
         let cache = Data::new(Mutex::new(cache));
         let client_mock = Arc::new(client_mock) as Arc<dyn SearxProvider>;
         let client_mock = Data::new(client_mock);
         let app_conf = Data::new(Mutex::new(AppConfig::default()));
-        populate_cache_if_needed(&cache, &client_mock, &app_conf).await;
+        populate_cache_if_needed(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
         let instances = &cache.lock().unwrap().instances;
         assert_eq!(instances.len(), 1);
         assert_eq!(instances[0], "instance".to_string());
@@ -176,16 +716,18 @@ pub(crate) mod tests {
     async fn populate_cache_if_needed_fetch_instances_not_be_called_test() {
         let mut client_mock = MockSearxProvider::new();
         client_mock.expect_fetch_instances().never();
-        let cache = Cache {
-            instances: vec!["1".to_string()],
-            creation_time: Instant::now(),
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+        let cache = Cache::new(
+            vec!["1".to_string()],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
         let cache = Data::new(Mutex::new(cache));
         let client_mock = Arc::new(client_mock) as Arc<dyn SearxProvider>;
         let client_mock = Data::new(client_mock);
         let app_conf = Data::new(Mutex::new(AppConfig::default()));
-        populate_cache_if_needed(&cache, &client_mock, &app_conf).await;
+        populate_cache_if_needed(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
         let instances = &cache.lock().unwrap().instances;
         assert_eq!(instances.len(), 1);
         assert_eq!(instances[0], "1".to_string());
@@ -198,19 +740,349 @@ pub(crate) mod tests {
         let creation_time = Instant::now();
 
         MockClock::advance(Duration::from_secs(1000));
-        let cache = Cache {
-            instances: vec!["1".to_string()],
+        let cache = Cache::new(
+            vec!["1".to_string()],
             creation_time,
-            ttl: Duration::from_secs(HOUR.into()),
-        };
+            Duration::from_secs(HOUR.into()),
+        );
         let cache = Data::new(Mutex::new(cache));
         let client_mock = Arc::new(client_mock) as Arc<dyn SearxProvider>;
         let client_mock = Data::new(client_mock);
         let app_conf = Data::new(Mutex::new(AppConfig::default()));
-        populate_cache_if_needed(&cache, &client_mock, &app_conf).await;
+        populate_cache_if_needed(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
 
         let instances = &cache.lock().unwrap().instances;
         assert_eq!(instances.len(), 1);
         assert_eq!(instances[0], "1".to_string());
     }
+    fn snapshot_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rsearx-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn failing_client() -> Data<Arc<dyn SearxProvider>> {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .returning(|_| Err(anyhow!("searx.space unreachable")));
+        Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>)
+    }
+
+    #[actix_rt::test]
+    async fn populate_cache_if_needed_writes_snapshot_test() {
+        let dir = snapshot_dir("snapshot-write");
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .return_once(fetch_instances_return_mock());
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let mut cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
+        cache.snapshot_path = Some(dir.join("snapshot.json"));
+        cache.stale = true;
+        let cache = Data::new(Mutex::new(cache));
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        populate_cache_if_needed(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
+
+        assert!(!cache.lock().unwrap().stale);
+        let snapshot = Snapshot::load(&dir.join("snapshot.json")).await.unwrap();
+        assert_eq!(snapshot.instances["instance"]["html"]["grade"], "C");
+        assert_eq!(snapshot.validators, instance_list().validators);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn refresh_instances_keeps_pool_when_fetch_fails_test() {
+        let creation_time = Instant::now();
+        MockClock::advance(Duration::from_secs(HOUR.into()) + Duration::from_secs(10));
+        let cache = Cache::new(
+            vec!["1".to_string()],
+            creation_time,
+            Duration::from_secs(HOUR.into()),
+        );
+        let cache = Data::new(Mutex::new(cache));
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        refresh_instances(&cache, &failing_client(), &app_conf)
+            .await
+            .unwrap();
+
+        let cache_guard = cache.lock().unwrap();
+        assert_eq!(cache_guard.instances, vec!["1".to_string()]);
+        assert!(cache_guard.stale);
+        assert!(!ttl_exceeded(&cache_guard));
+    }
+
+    #[actix_rt::test]
+    async fn refresh_instances_not_modified_extends_pool_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .withf(|validators| {
+                validators["https://searx.space/"].etag.as_deref() == Some("\"v1\"")
+            })
+            .return_once(|_| Ok(Fetched::NotModified));
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let creation_time = Instant::now();
+        MockClock::advance(Duration::from_secs(HOUR.into()) + Duration::from_secs(10));
+        let mut cache = Cache::new(
+            vec!["1".to_string()],
+            creation_time,
+            Duration::from_secs(HOUR.into()),
+        );
+        cache.validators = instance_list().validators;
+        cache.stale = true;
+        let cache = Data::new(Mutex::new(cache));
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        refresh_instances(&cache, &client_mock, &app_conf)
+            .await
+            .unwrap();
+
+        let cache_guard = cache.lock().unwrap();
+        assert_eq!(cache_guard.instances, vec!["1".to_string()]);
+        assert!(!cache_guard.stale);
+        assert!(!ttl_exceeded(&cache_guard));
+    }
+
+    #[actix_rt::test]
+    async fn populate_cache_if_needed_falls_back_to_snapshot_test() {
+        let dir = snapshot_dir("snapshot-fallback");
+        let path = dir.join("snapshot.json");
+        Snapshot::new(instance_list()).save(&path).await.unwrap();
+        let mut cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
+        cache.snapshot_path = Some(path);
+        let cache = Data::new(Mutex::new(cache));
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        populate_cache_if_needed(&cache, &failing_client(), &app_conf)
+            .await
+            .unwrap();
+
+        let cache_guard = cache.lock().unwrap();
+        assert_eq!(cache_guard.instances, vec!["instance".to_string()]);
+        assert!(cache_guard.stale);
+        assert!(cache_guard.fetched_at.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn populate_cache_if_needed_fails_without_snapshot_test() {
+        let cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(HOUR.into()));
+        let cache = Data::new(Mutex::new(cache));
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        assert!(
+            populate_cache_if_needed(&cache, &failing_client(), &app_conf)
+                .await
+                .is_err()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn fan_out_search_merges_responding_instances_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_get_instance_search_json()
+            .times(3)
+            .returning(|instance_url, query| {
+                if instance_url == "https://broken.org/" {
+                    return Err(anyhow::anyhow!("503"));
+                }
+                Ok(SearchResponse {
+                    query: query.q.clone(),
+                    instance: instance_url.to_string(),
+                    results: vec![SearchResult {
+                        url: "https://rust-lang.org/".to_string(),
+                        ..SearchResult::default()
+                    }],
+                })
+            });
+        let client_mock = Arc::new(client_mock) as Arc<dyn SearxProvider>;
+        let urls = vec![
+            "https://a.org/".to_string(),
+            "https://broken.org/".to_string(),
+            "https://b.org/".to_string(),
+        ];
+        let (response, latencies) = fan_out_search(
+            client_mock,
+            urls,
+            SearchParams::new("rust"),
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(
+            response.instances,
+            vec!["https://a.org/".to_string(), "https://b.org/".to_string()]
+        );
+        assert_eq!(
+            response.failed_instances,
+            vec!["https://broken.org/".to_string()]
+        );
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(response.results[0].instances, response.instances);
+        assert_eq!(latencies.len(), 2);
+        assert!(!latencies.contains_key("https://broken.org/"));
+    }
+
+    fn cache_with(instances: &[&str]) -> Data<Mutex<Cache>> {
+        Data::new(Mutex::new(Cache::new(
+            instances.iter().map(|url| url.to_string()).collect(),
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        )))
+    }
+
+    #[test]
+    fn select_url_from_cache_test() {
+        let cache = cache_with(&["a", "b", "c"]);
+        let breaker = BreakerConfig::default();
+        let round_robin: Vec<String> = (0..3)
+            .filter_map(|_| select_url_from_cache(&cache, &[], &breaker, Strategy::RoundRobin))
+            .collect();
+        assert_eq!(round_robin, vec!["a", "b", "c"]);
+
+        MockClock::advance(Duration::from_secs(1));
+        let url = select_url_from_cache(&cache, &[], &breaker, Strategy::RoundRobin);
+        assert_eq!(url.as_deref(), Some("a"));
+        let url = select_url_from_cache(&cache, &[], &breaker, Strategy::LeastRecentlyUsed);
+        assert_eq!(url.as_deref(), Some("b"));
+
+        let urls = select_urls_from_cache(&cache, 5, &breaker, Strategy::Uniform);
+        assert_eq!(urls.len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn search_with_failover_skips_failed_instances_test() {
+        let cache = cache_with(&["a", "b", "c"]);
+        let (result, tried) = search_with_failover(
+            &cache,
+            3,
+            Duration::from_secs(10),
+            &BreakerConfig::default(),
+            Strategy::Uniform,
+            None,
+            |url| async move {
+                if url == "c" {
+                    Ok(url)
+                } else {
+                    Err(anyhow!("{url} is down"))
+                }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), "c");
+        assert_eq!(tried.last().unwrap(), "c");
+        let unique: std::collections::HashSet<&String> = tried.iter().collect();
+        assert_eq!(unique.len(), tried.len());
+    }
+
+    #[actix_rt::test]
+    async fn search_with_failover_gives_up_test() {
+        let cache = cache_with(&["a", "b", "c", "d"]);
+        let (result, tried) = search_with_failover(
+            &cache,
+            2,
+            Duration::from_secs(10),
+            &BreakerConfig::default(),
+            Strategy::Uniform,
+            None,
+            |url| async move { Err::<(), _>(anyhow!("{url} is down")) },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(tried.len(), 2);
+        assert_ne!(tried[0], tried[1]);
+
+        let cache = cache_with(&["a"]);
+        let (result, tried) = search_with_failover(
+            &cache,
+            5,
+            Duration::from_secs(10),
+            &BreakerConfig::default(),
+            Strategy::Uniform,
+            None,
+            |url| async move { Err::<(), _>(anyhow!("{url} is down")) },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(tried, vec!["a".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn search_with_failover_retries_refusals_and_hangs_test() {
+        let cache = cache_with(&["a", "b", "c"]);
+        let (result, tried) = search_with_failover(
+            &cache,
+            3,
+            Duration::from_millis(20),
+            &BreakerConfig::default(),
+            Strategy::LeastRecentlyUsed,
+            None,
+            |url| async move {
+                match url.as_str() {
+                    "a" => Err(status_error(429)),
+                    "b" => std::future::pending().await,
+                    _ => Ok(url),
+                }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), "c");
+        assert_eq!(tried, vec!["a", "b", "c"]);
+        let cache_guard = cache.lock().unwrap();
+        assert_eq!(cache_guard.health["a"].consecutive_failures, 1);
+        assert_eq!(cache_guard.health["b"].consecutive_failures, 1);
+    }
+
+    #[actix_rt::test]
+    async fn search_with_failover_tries_preferred_first_test() {
+        let cache = cache_with(&["a", "b", "c"]);
+        let (result, tried) = search_with_failover(
+            &cache,
+            3,
+            Duration::from_secs(10),
+            &BreakerConfig::default(),
+            Strategy::Uniform,
+            Some("b".to_string()),
+            |url| async move { Ok(url) },
+        )
+        .await;
+        assert_eq!(result.unwrap(), "b");
+        assert_eq!(tried, vec!["b".to_string()]);
+
+        let (_, tried) = search_with_failover(
+            &cache,
+            1,
+            Duration::from_secs(10),
+            &BreakerConfig::default(),
+            Strategy::Uniform,
+            Some("not-in-pool".to_string()),
+            |url| async move { Ok(url) },
+        )
+        .await;
+        assert_ne!(tried, vec!["not-in-pool".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn search_with_failover_skips_open_preferred_test() {
+        let cache = cache_with(&["a", "b"]);
+        let breaker = BreakerConfig {
+            failure_threshold: 1,
+            ..BreakerConfig::default()
+        };
+        record_failure(&cache, "b", "timeout".to_string(), &breaker);
+        let (result, tried) = search_with_failover(
+            &cache,
+            3,
+            Duration::from_secs(10),
+            &breaker,
+            Strategy::Uniform,
+            Some("b".to_string()),
+            |url| async move { Ok(url) },
+        )
+        .await;
+        assert_eq!(result.unwrap(), "a");
+        assert_eq!(tried, vec!["a".to_string()]);
+    }
 }
@@ -0,0 +1,232 @@
+#[cfg(not(test))]
+use std::time::Instant;
+use std::{collections::HashMap, time::Duration};
+
+#[cfg(test)]
+use mock_instant::Instant;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Consecutive failures after which the breaker opens.
+    pub failure_threshold: u32,
+    /// How long an open breaker stays open before it becomes half-open.
+    pub cooldown_secs: u64,
+    /// Chance that a selection goes to a half-open instance as a probe.
+    pub probe_ratio: f64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_secs: 120,
+            probe_ratio: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Weight of the newest sample in the moving averages.
+pub const EWMA_ALPHA: f64 = 0.3;
+
+#[derive(Clone, Debug, Default)]
+pub struct InstanceHealth {
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Moving average of the response time of successful requests, as seen from here.
+    pub latency: Option<Duration>,
+    /// Moving average of successes (1) and failures (0).
+    pub success_rate: Option<f64>,
+    opened_at: Option<Instant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceStatus {
+    pub url: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub latency_ms: Option<u128>,
+    pub success_rate: Option<f64>,
+}
+
+impl InstanceHealth {
+    pub fn state(&self, config: &BreakerConfig) -> BreakerState {
+        match self.opened_at {
+            Some(opened_at) if opened_at.elapsed().as_secs() >= config.cooldown_secs => {
+                BreakerState::HalfOpen
+            }
+            Some(_) => BreakerState::Open,
+            None => BreakerState::Closed,
+        }
+    }
+
+    pub fn record_success(&mut self, latency: Option<Duration>) {
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+        self.success_rate = Some(ewma(self.success_rate, 1.0));
+        if let Some(latency) = latency {
+            let average = ewma(
+                self.latency.map(|latency| latency.as_secs_f64()),
+                latency.as_secs_f64(),
+            );
+            self.latency = Some(Duration::from_secs_f64(average));
+        }
+    }
+
+    /// A failure while half-open re-opens the breaker for another cooldown.
+    pub fn record_failure(&mut self, error: String, config: &BreakerConfig) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error);
+        self.success_rate = Some(ewma(self.success_rate, 0.0));
+        if self.consecutive_failures >= config.failure_threshold {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// The first sample is taken as is.
+fn ewma(average: Option<f64>, sample: f64) -> f64 {
+    match average {
+        Some(average) => EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * average,
+        None => sample,
+    }
+}
+
+/// Instances that may receive the next request: closed ones, or half-open ones when
+/// the probe roll succeeds (or nothing closed is left). Open breakers are never returned.
+pub fn healthy_candidates<'a>(
+    instances: &'a [String],
+    health: &HashMap<String, InstanceHealth>,
+    excluded: &[String],
+    config: &BreakerConfig,
+    rng: &mut impl Rng,
+) -> Vec<&'a String> {
+    let mut closed = Vec::new();
+    let mut half_open = Vec::new();
+    for url in instances.iter().filter(|url| !excluded.contains(url)) {
+        let state = health
+            .get(url)
+            .map(|health| health.state(config))
+            .unwrap_or(BreakerState::Closed);
+        match state {
+            BreakerState::Closed => closed.push(url),
+            BreakerState::HalfOpen => half_open.push(url),
+            BreakerState::Open => {}
+        }
+    }
+    if !half_open.is_empty()
+        && (closed.is_empty() || rng.gen_bool(config.probe_ratio.clamp(0.0, 1.0)))
+    {
+        half_open
+    } else {
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mock_instant::MockClock;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn config() -> BreakerConfig {
+        BreakerConfig {
+            failure_threshold: 2,
+            cooldown_secs: 60,
+            probe_ratio: 0.0,
+        }
+    }
+
+    #[test]
+    fn breaker_config_defaults_test() {
+        let config: BreakerConfig = serde_json::from_str(r#"{"cooldown_secs": 30}"#).unwrap();
+        assert_eq!(config.cooldown_secs, 30);
+        assert_eq!(
+            config.failure_threshold,
+            BreakerConfig::default().failure_threshold
+        );
+    }
+
+    #[test]
+    fn breaker_transitions_test() {
+        let config = config();
+        let mut health = InstanceHealth::default();
+        assert_eq!(health.state(&config), BreakerState::Closed);
+        health.record_failure("timeout".to_string(), &config);
+        assert_eq!(health.state(&config), BreakerState::Closed);
+        health.record_failure("timeout".to_string(), &config);
+        assert_eq!(health.state(&config), BreakerState::Open);
+        assert_eq!(health.last_error.as_deref(), Some("timeout"));
+
+        MockClock::advance(Duration::from_secs(61));
+        assert_eq!(health.state(&config), BreakerState::HalfOpen);
+        health.record_failure("still down".to_string(), &config);
+        assert_eq!(health.state(&config), BreakerState::Open);
+
+        MockClock::advance(Duration::from_secs(61));
+        assert_eq!(health.state(&config), BreakerState::HalfOpen);
+        health.record_success(None);
+        assert_eq!(health.state(&config), BreakerState::Closed);
+        assert_eq!(health.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn ewma_test() {
+        let config = config();
+        let mut health = InstanceHealth::default();
+        health.record_success(Some(Duration::from_millis(1000)));
+        assert_eq!(health.latency, Some(Duration::from_millis(1000)));
+        assert_eq!(health.success_rate, Some(1.0));
+        health.record_success(Some(Duration::from_millis(0)));
+        let latency = health.latency.unwrap().as_secs_f64();
+        assert!((latency - 0.7).abs() < 1e-6);
+        health.record_failure("timeout".to_string(), &config);
+        assert!((health.success_rate.unwrap() - 0.7).abs() < 1e-9);
+        health.record_success(None);
+        assert!((health.latency.unwrap().as_secs_f64() - latency).abs() < 1e-9);
+    }
+
+    #[test]
+    fn healthy_candidates_test() {
+        let config = config();
+        let instances = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut health = HashMap::new();
+        let mut open = InstanceHealth::default();
+        open.record_failure("err".to_string(), &config);
+        open.record_failure("err".to_string(), &config);
+        health.insert("a".to_string(), open);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let candidates = healthy_candidates(&instances, &health, &[], &config, &mut rng);
+        assert_eq!(candidates, vec!["b", "c"]);
+
+        let excluded = vec!["b".to_string(), "c".to_string()];
+        let candidates = healthy_candidates(&instances, &health, &excluded, &config, &mut rng);
+        assert!(candidates.is_empty());
+
+        MockClock::advance(Duration::from_secs(61));
+        let candidates = healthy_candidates(&instances, &health, &excluded, &config, &mut rng);
+        assert_eq!(candidates, vec!["a"]);
+
+        let probing = BreakerConfig {
+            probe_ratio: 1.0,
+            ..config
+        };
+        let candidates = healthy_candidates(&instances, &health, &[], &probing, &mut rng);
+        assert_eq!(candidates, vec!["a"]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+/// An instance record as published by searx.space. Every field is optional, unknown
+/// fields are ignored and a field of the wrong type is left unset, so changes upstream
+/// don't break parsing.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct InstanceInfo {
+    #[serde(deserialize_with = "lenient")]
+    pub network_type: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub version: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub html: Option<Graded>,
+    #[serde(deserialize_with = "lenient")]
+    pub tls: Option<Graded>,
+    #[serde(deserialize_with = "lenient")]
+    pub csp: Option<Graded>,
+    #[serde(deserialize_with = "lenient")]
+    pub http: Option<Graded>,
+    #[serde(deserialize_with = "lenient")]
+    pub timing: Option<Timing>,
+    #[serde(deserialize_with = "lenient")]
+    pub uptime: Option<Uptime>,
+    #[serde(deserialize_with = "lenient")]
+    pub network: Option<Network>,
+    #[serde(deserialize_with = "lenient")]
+    pub analytics: Option<bool>,
+    /// Listed as an official instance.
+    #[serde(deserialize_with = "lenient")]
+    pub main: Option<bool>,
+    /// Set on instances that skip the quality criteria of the filter, see
+    /// [`crate::instance_source::PINNED_KEY`].
+    #[serde(rename = "rsearx_pinned")]
+    pub pinned: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Graded {
+    #[serde(deserialize_with = "lenient")]
+    pub grade: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Timing {
+    #[serde(deserialize_with = "lenient")]
+    pub initial: Option<TimingStats>,
+    #[serde(deserialize_with = "lenient")]
+    pub search: Option<TimingStats>,
+    /// Searches answered by Google.
+    #[serde(deserialize_with = "lenient")]
+    pub search_go: Option<TimingStats>,
+    /// Searches answered by Wikipedia.
+    #[serde(deserialize_with = "lenient")]
+    pub search_wp: Option<TimingStats>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct TimingStats {
+    #[serde(deserialize_with = "lenient")]
+    pub success_percentage: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub all: Option<TimingValues>,
+}
+
+/// Response times in seconds.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct TimingValues {
+    #[serde(deserialize_with = "lenient")]
+    pub mean: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub median: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub stdev: Option<f64>,
+    /// Single measurement, published instead of the statistics for `initial`.
+    #[serde(deserialize_with = "lenient")]
+    pub value: Option<f64>,
+}
+
+/// Percentages of successful checks.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Uptime {
+    #[serde(deserialize_with = "lenient")]
+    pub uptime_day: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub uptime_week: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub uptime_month: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub uptime_year: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Network {
+    #[serde(deserialize_with = "lenient")]
+    pub ipv6: Option<bool>,
+    /// -1 when the hosting ASN belongs to a privacy hostile company, 0 when it doesn't
+    /// and 1 when that's unknown.
+    #[serde(deserialize_with = "lenient")]
+    pub asn_privacy: Option<i64>,
+}
+
+/// The requests searx.space measures response times of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimedRequest {
+    Initial,
+    Search,
+    SearchGo,
+    SearchWp,
+}
+
+impl InstanceInfo {
+    pub fn network_type(&self) -> &str {
+        self.network_type.as_deref().unwrap_or_default()
+    }
+
+    pub fn html_grade(&self) -> Option<&str> {
+        grade(&self.html)
+    }
+
+    pub fn tls_grade(&self) -> Option<&str> {
+        grade(&self.tls)
+    }
+
+    pub fn csp_grade(&self) -> Option<&str> {
+        grade(&self.csp)
+    }
+
+    pub fn http_grade(&self) -> Option<&str> {
+        grade(&self.http)
+    }
+
+    pub fn timing(&self, request: TimedRequest) -> Option<&TimingStats> {
+        let timing = self.timing.as_ref()?;
+        match request {
+            TimedRequest::Initial => timing.initial.as_ref(),
+            TimedRequest::Search => timing.search.as_ref(),
+            TimedRequest::SearchGo => timing.search_go.as_ref(),
+            TimedRequest::SearchWp => timing.search_wp.as_ref(),
+        }
+    }
+}
+
+impl TimingStats {
+    /// A single `value` stands in for the mean.
+    pub fn mean(&self) -> Option<f64> {
+        let all = self.all.as_ref()?;
+        all.mean.or(all.value)
+    }
+}
+
+/// Deserializes a field that is left unset when it has the wrong type, instead of failing
+/// the whole record. `parse_instances` reports these fields.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(Option::<T>::deserialize(value).unwrap_or_default())
+}
+
+fn grade(graded: &Option<Graded>) -> Option<&str> {
+    graded.as_ref()?.grade.as_deref()
+}
+
+/// Parsed instances by URL, ordered by URL.
+pub type Instances = BTreeMap<String, InstanceInfo>;
+
+/// A listed instance whose record could not be parsed.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct RejectedRecord {
+    pub url: String,
+    pub error: String,
+}
+
+/// A field of a listed instance that was left unset because it had the wrong type.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct MalformedField {
+    pub url: String,
+    /// Dotted path of the field, e.g. `timing.search.all.mean`.
+    pub field: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedListing {
+    pub instances: Instances,
+    pub rejected: Vec<RejectedRecord>,
+    pub malformed: Vec<MalformedField>,
+}
+
+/// Parses every record of a listing. Records that aren't objects and fields of the
+/// wrong type are logged and returned separately instead of being dropped silently.
+pub fn parse_instances(records: &Map<String, Value>) -> ParsedListing {
+    let mut parsed = ParsedListing::default();
+    for (url, record) in records {
+        match InstanceInfo::deserialize(record) {
+            Ok(info) => {
+                let mut unset = Vec::new();
+                if let Ok(value) = serde_json::to_value(&info) {
+                    unset_fields(&value, record, "", &mut unset);
+                }
+                if !unset.is_empty() {
+                    let fields: Vec<&str> = unset.iter().map(|(field, _)| field.as_str()).collect();
+                    warn!("ignoring malformed fields of {url}: {}", fields.join(", "));
+                }
+                parsed
+                    .malformed
+                    .extend(unset.into_iter().map(|(field, value)| MalformedField {
+                        url: url.clone(),
+                        field,
+                        value,
+                    }));
+                parsed.instances.insert(url.clone(), info);
+            }
+            Err(err) => {
+                warn!("skipping the record of {url}: {err}");
+                parsed.rejected.push(RejectedRecord {
+                    url: url.clone(),
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    parsed
+}
+
+/// Fields that are unset in `parsed` although `record` has a value for them, with that value.
+fn unset_fields(parsed: &Value, record: &Value, path: &str, unset: &mut Vec<(String, Value)>) {
+    let (parsed, record) = match (parsed, record) {
+        (Value::Object(parsed), Value::Object(record)) => (parsed, record),
+        _ => return,
+    };
+    for (key, parsed_value) in parsed {
+        let value = match record.get(key) {
+            Some(value) if !value.is_null() => value,
+            _ => continue,
+        };
+        let field = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        if parsed_value.is_null() {
+            unset.push((field, value.clone()));
+        } else {
+            unset_fields(parsed_value, value, &field, unset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_searx_space_record_test() {
+        let record = json!({
+            "analytics": false,
+            "comments": [],
+            "main": true,
+            "network_type": "normal",
+            "version": "2024.10.17+e7a2b6f",
+            "html": { "grade": "V", "ressources": {} },
+            "tls": { "grade": "A+", "certificate": { "issuer": "R3" } },
+            "csp": { "grade": "F" },
+            "http": { "grade": "A", "status_code": 200 },
+            "network": { "ipv6": true, "asn_privacy": 0, "ips": {} },
+            "timing": {
+                "initial": { "success_percentage": 100.0, "all": { "value": 0.2 } },
+                "search": {
+                    "success_percentage": 98,
+                    "all": { "mean": 0.52, "median": 0.5, "stdev": 0.1 },
+                    "server": { "mean": 0.4 }
+                },
+                "search_go": null
+            },
+            "uptime": { "uptimeDay": 100.0, "uptimeWeek": 99.5, "uptimeMonth": 99, "uptimeYear": null }
+        });
+        let info = InstanceInfo::deserialize(&record).unwrap();
+        assert_eq!(info.network_type(), "normal");
+        assert_eq!(info.html_grade(), Some("V"));
+        assert_eq!(info.tls_grade(), Some("A+"));
+        assert_eq!(info.http_grade(), Some("A"));
+        assert_eq!(info.main, Some(true));
+        assert_eq!(info.network.as_ref().unwrap().asn_privacy, Some(0));
+        let search = info.timing(TimedRequest::Search).unwrap();
+        assert_eq!(search.mean(), Some(0.52));
+        assert_eq!(search.success_percentage, Some(98.0));
+        assert!(info.timing(TimedRequest::SearchGo).is_none());
+        let uptime = info.uptime.unwrap();
+        assert_eq!(uptime.uptime_month, Some(99.0));
+        assert_eq!(uptime.uptime_year, None);
+        assert!(!info.pinned);
+    }
+
+    #[test]
+    fn parse_mistyped_fields_test() {
+        let record = json!({
+            "network_type": 1,
+            "main": "yes",
+            "tls": { "grade": "A" },
+            "network": { "ipv6": true, "asn_privacy": "unknown" },
+            "timing": {
+                "search": { "success_percentage": 95.0, "all": { "mean": "fast", "median": 0.4 } }
+            },
+            "uptime": [99.0]
+        });
+        let info = InstanceInfo::deserialize(&record).unwrap();
+        assert_eq!(info.network_type, None);
+        assert_eq!(info.main, None);
+        assert_eq!(info.tls_grade(), Some("A"));
+        assert_eq!(info.network.as_ref().unwrap().ipv6, Some(true));
+        assert_eq!(info.network.as_ref().unwrap().asn_privacy, None);
+        let search = info.timing(TimedRequest::Search).unwrap();
+        assert_eq!(search.mean(), None);
+        assert_eq!(search.success_percentage, Some(95.0));
+        assert_eq!(search.all.as_ref().unwrap().median, Some(0.4));
+        assert_eq!(info.uptime, None);
+    }
+
+    #[test]
+    fn parse_instances_reports_bad_records_test() {
+        let mut records = Map::new();
+        records.insert("https://a.org/".to_string(), json!({}));
+        records.insert(
+            "https://b.org/".to_string(),
+            json!({ "network_type": "normal", "rsearx_pinned": true }),
+        );
+        records.insert(
+            "https://c.org/".to_string(),
+            json!({ "timing": { "search": { "all": { "mean": "fast" } } } }),
+        );
+        records.insert("https://d.org/".to_string(), json!("not a record"));
+        let parsed = parse_instances(&records);
+        assert_eq!(
+            parsed.instances.keys().collect::<Vec<_>>(),
+            vec!["https://a.org/", "https://b.org/", "https://c.org/"]
+        );
+        assert!(parsed.instances["https://b.org/"].pinned);
+        let rejected: Vec<&str> = parsed
+            .rejected
+            .iter()
+            .map(|record| record.url.as_str())
+            .collect();
+        assert_eq!(rejected, vec!["https://d.org/"]);
+        assert_eq!(
+            parsed.malformed,
+            vec![MalformedField {
+                url: "https://c.org/".to_string(),
+                field: "timing.search.all.mean".to_string(),
+                value: json!("fast"),
+            }]
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+
+#[cfg(test)]
+use mockall::automock;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{info, warn};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::fs;
+
+use crate::proxy::network_type;
+
+/// Marks instances that skip the quality criteria of the filter.
+pub static PINNED_KEY: &str = "rsearx_pinned";
+
+/// Where instance lists come from. When several sources list the same instance
+/// the record of the source listed first wins.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceConfig {
+    /// A searx.space-like site serving `data/instances.json`.
+    Remote { url: String },
+    /// A file in the instances.json layout, either the whole document or just its `instances` map.
+    File { path: String },
+    /// Instance URLs from the config. They carry no metadata, so they are pinned: only the
+    /// network filter applies to them.
+    Static { instances: Vec<String> },
+}
+
+pub fn default_sources() -> Vec<SourceConfig> {
+    vec![SourceConfig::Remote {
+        url: "https://searx.space/".to_string(),
+    }]
+}
+
+/// Cache validators of the last listing a source delivered.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Fetched<T> {
+    Modified(T),
+    /// Nothing changed since the validators were issued.
+    NotModified,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Listing {
+    pub instances: Map<String, Value>,
+    pub validators: Validators,
+}
+
+/// Merged listing of all sources with their validators by source name.
+#[derive(Debug, PartialEq)]
+pub struct InstanceList {
+    pub instances: Map<String, Value>,
+    pub validators: HashMap<String, Validators>,
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait InstanceSource: Sync + Send {
+    fn name(&self) -> String;
+    /// Without validators the full listing is always delivered.
+    async fn fetch(&self, validators: Option<Validators>) -> anyhow::Result<Fetched<Listing>>;
+}
+
+pub struct RemoteSource {
+    client: Client,
+    base_url: Url,
+}
+
+pub struct FileSource {
+    path: PathBuf,
+}
+
+pub struct StaticSource {
+    instances: Vec<String>,
+}
+
+impl SourceConfig {
+    pub fn build(&self, client: &Client) -> anyhow::Result<Box<dyn InstanceSource>> {
+        let source: Box<dyn InstanceSource> = match self {
+            SourceConfig::Remote { url } => Box::new(RemoteSource {
+                client: client.clone(),
+                base_url: Url::parse(url)?,
+            }),
+            SourceConfig::File { path } => Box::new(FileSource {
+                path: PathBuf::from(path),
+            }),
+            SourceConfig::Static { instances } => Box::new(StaticSource {
+                instances: instances.clone(),
+            }),
+        };
+        Ok(source)
+    }
+}
+
+fn instances_from_document(body: Value) -> anyhow::Result<Map<String, Value>> {
+    match body {
+        Value::Object(mut document) => match document.remove("instances") {
+            Some(Value::Object(instances)) => Ok(instances),
+            Some(_) => Err(anyhow!("no instances prop")),
+            None => Ok(document),
+        },
+        _ => Err(anyhow!("no instances prop")),
+    }
+}
+
+#[async_trait]
+impl InstanceSource for RemoteSource {
+    fn name(&self) -> String {
+        self.base_url.to_string()
+    }
+    async fn fetch(&self, validators: Option<Validators>) -> anyhow::Result<Fetched<Listing>> {
+        let url = Url::join(&self.base_url, "data/instances.json")?;
+        let mut request = self.client.get(url);
+        if let Some(validators) = &validators {
+            if let Some(etag) = &validators.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        let response = response.error_for_status()?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body: Value = response.json().await?;
+        Ok(Fetched::Modified(Listing {
+            instances: instances_from_document(body)?,
+            validators,
+        }))
+    }
+}
+
+#[async_trait]
+impl InstanceSource for FileSource {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+    /// The file's modification time serves as its validator.
+    async fn fetch(&self, validators: Option<Validators>) -> anyhow::Result<Fetched<Listing>> {
+        let modified = fs::metadata(&self.path)
+            .await?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos()
+            .to_string();
+        let validators_now = Validators {
+            last_modified: Some(modified),
+            ..Validators::default()
+        };
+        if validators.as_ref() == Some(&validators_now) {
+            return Ok(Fetched::NotModified);
+        }
+        let content = fs::read_to_string(&self.path).await?;
+        Ok(Fetched::Modified(Listing {
+            instances: instances_from_document(serde_json::from_str(&content)?)?,
+            validators: validators_now,
+        }))
+    }
+}
+
+#[async_trait]
+impl InstanceSource for StaticSource {
+    fn name(&self) -> String {
+        "static".to_string()
+    }
+    /// The hash of the configured list serves as its validator.
+    async fn fetch(&self, validators: Option<Validators>) -> anyhow::Result<Fetched<Listing>> {
+        let mut hasher = DefaultHasher::new();
+        self.instances.hash(&mut hasher);
+        let validators_now = Validators {
+            etag: Some(format!("{:x}", hasher.finish())),
+            ..Validators::default()
+        };
+        if validators.as_ref() == Some(&validators_now) {
+            return Ok(Fetched::NotModified);
+        }
+        let mut instances = Map::new();
+        for url in &self.instances {
+            let url = match normalize_instance_url(url) {
+                Some(url) => url,
+                None => {
+                    warn!("skipping the invalid static instance URL {url:?}");
+                    continue;
+                }
+            };
+            let info = json!({
+                "network_type": network_type(&url),
+                PINNED_KEY: true,
+            });
+            instances.insert(url, info);
+        }
+        Ok(Fetched::Modified(Listing {
+            instances,
+            validators: validators_now,
+        }))
+    }
+}
+
+/// Fetches every source and merges them in order. Failing sources are skipped,
+/// the call only fails when none of them delivered. When nothing changed since
+/// `validators` were issued the result is `NotModified`; when only some sources
+/// changed the unchanged ones are fetched again in full so the merge is complete.
+pub async fn fetch_all(
+    sources: &[Box<dyn InstanceSource>],
+    validators: &HashMap<String, Validators>,
+) -> anyhow::Result<Fetched<InstanceList>> {
+    let mut listings = Vec::new();
+    let mut last_error = None;
+    for source in sources {
+        match source.fetch(validators.get(&source.name()).cloned()).await {
+            Ok(fetched) => listings.push((source, fetched)),
+            Err(err) => {
+                warn!("source {} failed: {err}", source.name());
+                last_error = Some(err);
+            }
+        }
+    }
+    if listings.is_empty() {
+        return Err(last_error.unwrap_or_else(|| anyhow!("no instance sources configured")));
+    }
+    if listings
+        .iter()
+        .all(|(_, fetched)| *fetched == Fetched::NotModified)
+    {
+        info!("no source changed since the last fetch");
+        return Ok(Fetched::NotModified);
+    }
+    let mut merged = InstanceList {
+        instances: Map::new(),
+        validators: HashMap::new(),
+    };
+    for (source, fetched) in listings {
+        let listing = match fetched {
+            Fetched::Modified(listing) => listing,
+            Fetched::NotModified => match source.fetch(None).await {
+                Ok(Fetched::Modified(listing)) => listing,
+                Ok(Fetched::NotModified) => continue,
+                Err(err) => {
+                    warn!("source {} failed: {err}", source.name());
+                    continue;
+                }
+            },
+        };
+        info!(
+            "source {} listed {} instances",
+            source.name(),
+            listing.instances.len()
+        );
+        for (url, info) in listing.instances {
+            match normalize_instance_url(&url) {
+                Some(url) => {
+                    merged.instances.entry(url).or_insert(info);
+                }
+                None => warn!(
+                    "source {} listed an invalid instance URL {url:?}, skipping it",
+                    source.name()
+                ),
+            }
+        }
+        merged.validators.insert(source.name(), listing.validators);
+    }
+    Ok(Fetched::Modified(merged))
+}
+
+/// Canonical form of an instance URL, so the same instance listed by several
+/// sources is merged once. The path ends with a slash since search URLs are
+/// joined onto it. `None` for anything that isn't an http(s) URL.
+fn normalize_instance_url(url: &str) -> Option<String> {
+    let mut url = Url::parse(url.trim()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return None;
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Some(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(instances: Map<String, Value>) -> Fetched<Listing> {
+        Fetched::Modified(Listing {
+            instances,
+            validators: Validators::default(),
+        })
+    }
+
+    fn source_returning(
+        name: &'static str,
+        result: fn() -> anyhow::Result<Fetched<Listing>>,
+    ) -> Box<dyn InstanceSource> {
+        let mut source = MockInstanceSource::new();
+        source.expect_name().returning(move || name.to_string());
+        source.expect_fetch().return_once(move |_| result());
+        Box::new(source)
+    }
+
+    #[actix_rt::test]
+    async fn fetch_all_merges_with_precedence_test() {
+        let sources = vec![
+            source_returning("private", || {
+                let mut m = Map::new();
+                m.insert("https://a.org/".to_string(), json!({ "from": "private" }));
+                Ok(listing(m))
+            }),
+            source_returning("broken", || Err(anyhow!("timeout"))),
+            source_returning("public", || {
+                let mut m = Map::new();
+                m.insert("https://a.org/".to_string(), json!({ "from": "public" }));
+                m.insert("https://b.org/".to_string(), json!({ "from": "public" }));
+                Ok(listing(m))
+            }),
+        ];
+        let merged = match fetch_all(&sources, &HashMap::new()).await.unwrap() {
+            Fetched::Modified(merged) => merged,
+            Fetched::NotModified => panic!("expected a listing"),
+        };
+        assert_eq!(merged.instances.len(), 2);
+        assert_eq!(merged.instances["https://a.org/"]["from"], "private");
+        assert_eq!(merged.instances["https://b.org/"]["from"], "public");
+        assert_eq!(merged.validators.len(), 2);
+        assert!(!merged.validators.contains_key("broken"));
+    }
+
+    #[actix_rt::test]
+    async fn fetch_all_normalizes_urls_test() {
+        let sources = vec![
+            source_returning("private", || {
+                let mut m = Map::new();
+                m.insert("https://A.org".to_string(), json!({ "from": "private" }));
+                m.insert("not a url".to_string(), json!({}));
+                m.insert("ftp://c.org/".to_string(), json!({}));
+                Ok(listing(m))
+            }),
+            source_returning("public", || {
+                let mut m = Map::new();
+                m.insert("https://a.org/".to_string(), json!({ "from": "public" }));
+                m.insert("https://b.org/searx".to_string(), json!({}));
+                Ok(listing(m))
+            }),
+        ];
+        let merged = match fetch_all(&sources, &HashMap::new()).await.unwrap() {
+            Fetched::Modified(merged) => merged,
+            Fetched::NotModified => panic!("expected a listing"),
+        };
+        assert_eq!(
+            merged.instances.keys().collect::<Vec<_>>(),
+            vec!["https://a.org/", "https://b.org/searx/"]
+        );
+        assert_eq!(merged.instances["https://a.org/"]["from"], "private");
+    }
+
+    #[actix_rt::test]
+    async fn fetch_all_fails_when_every_source_fails_test() {
+        let sources = vec![source_returning("broken", || Err(anyhow!("timeout")))];
+        assert!(fetch_all(&sources, &HashMap::new()).await.is_err());
+        assert!(fetch_all(&[], &HashMap::new()).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn fetch_all_not_modified_test() {
+        let sources = vec![
+            source_returning("public", || Ok(Fetched::NotModified)),
+            source_returning("broken", || Err(anyhow!("timeout"))),
+        ];
+        assert_eq!(
+            fetch_all(&sources, &HashMap::new()).await.unwrap(),
+            Fetched::NotModified
+        );
+    }
+
+    #[actix_rt::test]
+    async fn fetch_all_refetches_unchanged_sources_test() {
+        let mut unchanged = MockInstanceSource::new();
+        unchanged.expect_name().returning(|| "public".to_string());
+        unchanged
+            .expect_fetch()
+            .withf(|validators| validators.is_some())
+            .return_once(|_| Ok(Fetched::NotModified));
+        unchanged
+            .expect_fetch()
+            .withf(|validators| validators.is_none())
+            .return_once(|_| {
+                let mut m = Map::new();
+                m.insert("https://b.org/".to_string(), json!({}));
+                Ok(listing(m))
+            });
+        let sources = vec![
+            source_returning("private", || {
+                let mut m = Map::new();
+                m.insert("https://a.org/".to_string(), json!({}));
+                Ok(listing(m))
+            }),
+            Box::new(unchanged) as Box<dyn InstanceSource>,
+        ];
+        let mut validators = HashMap::new();
+        validators.insert("public".to_string(), Validators::default());
+        match fetch_all(&sources, &validators).await.unwrap() {
+            Fetched::Modified(merged) => assert_eq!(merged.instances.len(), 2),
+            Fetched::NotModified => panic!("expected a listing"),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn file_source_uses_modification_time_test() {
+        let path = std::env::temp_dir().join(format!("rsearx-source-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "https://a.org/": {} }"#)
+            .await
+            .unwrap();
+        let source = FileSource { path: path.clone() };
+        let validators = match source.fetch(None).await.unwrap() {
+            Fetched::Modified(listing) => listing.validators,
+            Fetched::NotModified => panic!("expected a listing"),
+        };
+        assert!(validators.last_modified.is_some());
+        assert_eq!(
+            source.fetch(Some(validators)).await.unwrap(),
+            Fetched::NotModified
+        );
+        fs::remove_file(&path).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn static_source_test() {
+        let source = StaticSource {
+            instances: vec![
+                "https://private.example.org".to_string(),
+                "http://abc.onion/".to_string(),
+                "not a url".to_string(),
+            ],
+        };
+        let (instances, validators) = match source.fetch(None).await.unwrap() {
+            Fetched::Modified(listing) => (listing.instances, listing.validators),
+            Fetched::NotModified => panic!("expected a listing"),
+        };
+        assert_eq!(
+            source.fetch(Some(validators)).await.unwrap(),
+            Fetched::NotModified
+        );
+        assert_eq!(
+            instances["https://private.example.org/"],
+            json!({ "network_type": "normal", "rsearx_pinned": true })
+        );
+        assert_eq!(instances["http://abc.onion/"]["network_type"], "tor");
+        assert_eq!(instances.len(), 2);
+    }
+
+    #[test]
+    fn instances_from_document_test() {
+        let document = json!({ "metadata": {}, "instances": { "https://a.org/": {} } });
+        assert_eq!(instances_from_document(document).unwrap().len(), 1);
+        let bare = json!({ "https://a.org/": {}, "https://b.org/": {} });
+        assert_eq!(instances_from_document(bare).unwrap().len(), 2);
+        assert!(instances_from_document(json!({ "instances": [] })).is_err());
+    }
+}
@@ -1,11 +1,25 @@
 use filter::Filter;
+use fingerprint::FingerprintConfig;
+use health::{BreakerConfig, InstanceHealth};
+use instance_info::{MalformedField, RejectedRecord};
+use instance_source::{default_sources, SourceConfig, Validators};
+use merge::FanOutConfig;
 #[cfg(test)]
 use mock_instant::Instant;
+use prober::{probe_periodically, ProbeConfig};
+use proxy::ProxyConfig;
+use refresh::{refresh_periodically, RefreshConfig};
+use rewriter::RewriteConfig;
+use search_params::SearchDefaults;
+use selection::{PublishedStats, SelectionState, Strategy};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::{self, File},
+    io,
+    path::PathBuf,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use actix_files as afs;
@@ -18,28 +32,86 @@ use log::*;
 use simplelog::*;
 #[cfg(not(test))]
 use std::time::Instant;
+use tokio::sync::Mutex as AsyncMutex;
 
-use handlers::search::search;
+use handlers::{
+    api::{api_fan_out_search, api_instances, api_search, api_status},
+    search::search,
+    search_helpers::{load_snapshot, reachable_filter},
+};
 use searx_client::SearxClient;
 
-use crate::{
-    frontend_manager::manager::{Executor},
-    searx_client::SearxProvider,
-};
+use crate::{frontend_manager::manager::Executor, searx_client::SearxProvider};
 use args::parse;
 use handlers::save::save;
 
 mod args;
 mod filter;
+mod fingerprint;
 mod frontend_manager;
+mod grade;
 mod handlers;
+mod health;
+mod instance_info;
+mod instance_source;
+mod merge;
+mod prober;
+mod proxy;
+mod refresh;
+mod rewriter;
+mod search_params;
+mod search_results;
 mod searx_client;
+mod selection;
+mod snapshot;
+mod version;
 
 #[derive(Debug)]
 pub struct Cache {
     creation_time: Instant,
     ttl: Duration,
     instances: Vec<String>,
+    health: HashMap<String, InstanceHealth>,
+    published: HashMap<String, PublishedStats>,
+    /// Records of the last listing that could not be parsed.
+    rejected: Vec<RejectedRecord>,
+    /// Fields of the last listing that were left unset because they had the wrong type.
+    malformed: Vec<MalformedField>,
+    selection: SelectionState,
+    /// When the prober last sent a probe to an instance.
+    probed_at: HashMap<String, Instant>,
+    /// The pool comes from the snapshot because the sources could not be fetched.
+    stale: bool,
+    fetched_at: Option<SystemTime>,
+    snapshot_path: Option<PathBuf>,
+    /// Sent along with the next fetch so unchanged sources can answer 304.
+    validators: HashMap<String, Validators>,
+    /// Held while the instance list is fetched, keeps the error of the last refresh.
+    refresh_gate: Arc<AsyncMutex<Option<String>>>,
+    /// Completed refreshes, tells a request that waited at the gate that it can share the result.
+    refreshes: u64,
+}
+
+impl Cache {
+    pub fn new(instances: Vec<String>, creation_time: Instant, ttl: Duration) -> Self {
+        Self {
+            creation_time,
+            ttl,
+            instances,
+            health: HashMap::new(),
+            published: HashMap::new(),
+            rejected: Vec::new(),
+            malformed: Vec::new(),
+            selection: SelectionState::default(),
+            probed_at: HashMap::new(),
+            stale: false,
+            fetched_at: None,
+            snapshot_path: None,
+            validators: HashMap::new(),
+            refresh_gate: Arc::new(AsyncMutex::new(None)),
+            refreshes: 0,
+        }
+    }
 }
 
 pub const HOUR: u32 = 60 * 60;
@@ -48,12 +120,26 @@ pub const HOUR: u32 = 60 * 60;
 pub struct AppConfig {
     server_conf: Option<String>,
     filter: Option<Filter>,
+    fan_out: Option<FanOutConfig>,
+    max_attempts: Option<usize>,
+    /// How long one instance may take to answer before the next one is tried.
+    attempt_timeout_ms: Option<u64>,
+    breaker: Option<BreakerConfig>,
+    search_defaults: Option<SearchDefaults>,
+    rewrite: Option<RewriteConfig>,
+    fingerprint: Option<FingerprintConfig>,
+    proxy: Option<ProxyConfig>,
+    sources: Option<Vec<SourceConfig>>,
+    snapshot_path: Option<String>,
+    refresh: Option<RefreshConfig>,
+    strategy: Option<Strategy>,
+    probe: Option<ProbeConfig>,
 }
 
 pub static CONFIG_FILENAME: &str = "config.json";
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> io::Result<()> {
     CombinedLogger::init(vec![
         TermLogger::new(
             LevelFilter::Info,
@@ -69,9 +155,14 @@ async fn main() -> std::io::Result<()> {
     ])
     .unwrap();
     info!("Logger initialized!");
-    let app_config = fs::read_to_string(CONFIG_FILENAME)
-        .map(|content| serde_json::from_str::<AppConfig>(&content).unwrap_or_default())
-        .unwrap_or_default();
+    // a config that doesn't parse would be overwritten with defaults by the next save
+    let app_config = match fs::read_to_string(CONFIG_FILENAME) {
+        Ok(content) => serde_json::from_str::<AppConfig>(&content).map_err(|err| {
+            error!("could not parse {CONFIG_FILENAME}: {err}");
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        })?,
+        Err(_) => AppConfig::default(),
+    };
     let should_download = parse().download;
     if should_download {
         let mut executor = Executor::new_supplied();
@@ -80,21 +171,58 @@ async fn main() -> std::io::Result<()> {
         info!("Downloading done");
     }
     info!("app_conf: {app_config:?}");
-    let base_url = "https://searx.space/".to_string();
-    let client = SearxClient::new(base_url);
+    let sources = app_config.sources.clone().unwrap_or_else(default_sources);
+    let client = SearxClient::new(
+        &sources,
+        app_config.fingerprint.clone().unwrap_or_default(),
+        &app_config.proxy.clone().unwrap_or_default(),
+    )
+    .expect("invalid proxy or instance source configuration");
     let client: Data<Arc<dyn SearxProvider>> = Data::new(Arc::new(client));
-    let cache = Cache {
-        instances: Vec::new(),
-        creation_time: Instant::now(),
-        ttl: Duration::from_secs(HOUR.into()),
-    };
+    let refresh = app_config.refresh.clone().unwrap_or_default();
+    let mut cache = Cache::new(
+        Vec::new(),
+        Instant::now(),
+        Duration::from_secs(refresh.interval_secs),
+    );
+    cache.snapshot_path = Some(PathBuf::from(
+        app_config
+            .snapshot_path
+            .as_deref()
+            .unwrap_or(snapshot::SNAPSHOT_FILENAME),
+    ));
     let cache = Data::new(Mutex::new(cache));
+    if let Err(err) = load_snapshot(&cache, &reachable_filter(&app_config), Duration::ZERO).await {
+        info!("no instance snapshot loaded: {err}");
+    }
     let app_config = Data::new(Mutex::new(app_config));
+    actix_rt::spawn(refresh_periodically(
+        cache.clone(),
+        client.clone(),
+        app_config.clone(),
+    ));
+    let probe_enabled = app_config
+        .lock()
+        .unwrap()
+        .probe
+        .as_ref()
+        .is_some_and(|probe| probe.enabled);
+    if probe_enabled {
+        actix_rt::spawn(probe_periodically(
+            cache.clone(),
+            client.clone(),
+            app_config.clone(),
+        ));
+    }
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .route("/search", web::get().to(search))
             .route("/save", web::post().to(save))
+            .route("/api/search", web::get().to(api_search))
+            .route("/api/search/fan-out", web::get().to(api_fan_out_search))
+            .route("/api/instances", web::get().to(api_instances))
+            .route("/api/status", web::get().to(api_status))
             .service(afs::Files::new("/", "./web").index_file("index.html")) // this has to be called after all other routes
             .app_data(client.clone())
             .app_data(cache.clone())
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::search_results::{SearchResponse, SearchResult};
+
+/// Rank constant of the reciprocal rank fusion, 60 is the value from the original paper.
+const RRF_K: f64 = 60.0;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct FanOutConfig {
+    pub instances: usize,
+    pub deadline_ms: u64,
+}
+
+impl Default for FanOutConfig {
+    fn default() -> Self {
+        Self {
+            instances: 3,
+            deadline_ms: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Clone, PartialEq)]
+pub struct MergedResult {
+    #[serde(flatten)]
+    pub result: SearchResult,
+    pub fusion_score: f64,
+    pub instances: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Clone, PartialEq)]
+pub struct MergedSearchResponse {
+    pub query: String,
+    pub instances: Vec<String>,
+    pub failed_instances: Vec<String>,
+    pub results: Vec<MergedResult>,
+}
+
+/// Key used to detect the same result coming from different instances or engines.
+/// Scheme, `www.`, fragment, trailing slash and `utm_*` tracking params are ignored.
+pub fn normalize_url(url: &str) -> String {
+    let parsed = match Url::parse(url.trim()) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.trim().to_lowercase(),
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let path = parsed.path().trim_end_matches('/');
+    let mut pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    pairs.sort();
+    let query = pairs
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<String>>()
+        .join("&");
+    let port = parsed
+        .port()
+        .map(|port| format!(":{port}"))
+        .unwrap_or_default();
+    if query.is_empty() {
+        format!("{host}{port}{path}")
+    } else {
+        format!("{host}{port}{path}?{query}")
+    }
+}
+
+/// Merges responses with reciprocal rank fusion: every instance adds `1 / (RRF_K + rank)`
+/// to a result so that results ranked high by many instances come first. An instance listing
+/// the same result twice only counts once, at its best rank.
+pub fn merge_responses(responses: &[SearchResponse]) -> Vec<MergedResult> {
+    let mut merged: Vec<MergedResult> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for response in responses {
+        let mut scored: HashSet<String> = HashSet::new();
+        for (rank, result) in response.results.iter().enumerate() {
+            let key = normalize_url(&result.url);
+            let rank_score = if scored.insert(key.clone()) {
+                1.0 / (RRF_K + rank as f64 + 1.0)
+            } else {
+                0.0
+            };
+            match positions.get(&key) {
+                Some(&position) => {
+                    let existing = &mut merged[position];
+                    existing.fusion_score += rank_score;
+                    if !existing.instances.contains(&response.instance) {
+                        existing.instances.push(response.instance.clone());
+                    }
+                    for engine in &result.engines {
+                        if !existing.result.engines.contains(engine) {
+                            existing.result.engines.push(engine.clone());
+                        }
+                    }
+                    if existing.result.content.len() < result.content.len() {
+                        existing.result.content = result.content.clone();
+                    }
+                    existing.result.score = existing.result.score.max(result.score);
+                }
+                None => {
+                    positions.insert(key, merged.len());
+                    merged.push(MergedResult {
+                        result: result.clone(),
+                        fusion_score: rank_score,
+                        instances: vec![response.instance.clone()],
+                    });
+                }
+            }
+        }
+    }
+    merged.sort_by(|a, b| {
+        b.fusion_score
+            .partial_cmp(&a.fusion_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| {
+                b.result
+                    .score
+                    .partial_cmp(&a.result.score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(url: &str, engine: &str) -> SearchResult {
+        SearchResult {
+            title: url.to_string(),
+            url: url.to_string(),
+            engines: vec![engine.to_string()],
+            score: 1.0,
+            ..SearchResult::default()
+        }
+    }
+
+    #[test]
+    fn fan_out_config_defaults_test() {
+        let config: FanOutConfig = serde_json::from_str(r#"{"instances": 5}"#).unwrap();
+        assert_eq!(config.instances, 5);
+        assert_eq!(config.deadline_ms, FanOutConfig::default().deadline_ms);
+    }
+
+    #[test]
+    fn normalize_url_test() {
+        assert_eq!(
+            normalize_url("https://www.Rust-Lang.org/learn/?utm_source=x&b=2&a=1#top"),
+            "rust-lang.org/learn?a=1&b=2"
+        );
+        assert_eq!(
+            normalize_url("http://rust-lang.org/learn"),
+            normalize_url("https://www.rust-lang.org/learn/")
+        );
+        assert_ne!(
+            normalize_url("https://rust-lang.org:8080/"),
+            normalize_url("https://rust-lang.org/")
+        );
+        assert_eq!(normalize_url(" not a url "), "not a url");
+    }
+
+    #[test]
+    fn merge_responses_test() {
+        let first = SearchResponse {
+            query: "rust".to_string(),
+            instance: "https://a.org/".to_string(),
+            results: vec![
+                result("https://only-a.org/", "bing"),
+                result("https://rust-lang.org/", "google"),
+            ],
+        };
+        let second = SearchResponse {
+            query: "rust".to_string(),
+            instance: "https://b.org/".to_string(),
+            results: vec![
+                result("https://www.rust-lang.org", "duckduckgo"),
+                result("https://only-b.org/", "bing"),
+            ],
+        };
+        let merged = merge_responses(&[first, second]);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].result.url, "https://rust-lang.org/");
+        assert_eq!(
+            merged[0].instances,
+            vec!["https://a.org/".to_string(), "https://b.org/".to_string()]
+        );
+        assert_eq!(
+            merged[0].result.engines,
+            vec!["google".to_string(), "duckduckgo".to_string()]
+        );
+        assert_eq!(merged[1].result.url, "https://only-a.org/");
+        assert_eq!(merged[2].result.url, "https://only-b.org/");
+        assert!(merged[0].fusion_score > merged[1].fusion_score);
+    }
+
+    #[test]
+    fn merge_responses_counts_duplicates_once_test() {
+        let first = SearchResponse {
+            query: "rust".to_string(),
+            instance: "https://a.org/".to_string(),
+            results: vec![
+                result("https://spam.org/", "bing"),
+                result("https://www.spam.org/", "google"),
+                result("https://rust-lang.org/", "bing"),
+            ],
+        };
+        let second = SearchResponse {
+            query: "rust".to_string(),
+            instance: "https://b.org/".to_string(),
+            results: vec![
+                result("https://only-b.org/", "bing"),
+                result("https://rust-lang.org/", "duckduckgo"),
+            ],
+        };
+        let merged = merge_responses(&[first, second]);
+        assert_eq!(merged[0].result.url, "https://rust-lang.org/");
+        let spam = merged
+            .iter()
+            .find(|merged| merged.result.url == "https://spam.org/")
+            .unwrap();
+        assert_eq!(spam.fusion_score, 1.0 / (RRF_K + 1.0));
+        assert_eq!(
+            spam.result.engines,
+            vec!["bing".to_string(), "google".to_string()]
+        );
+    }
+}
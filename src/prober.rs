@@ -0,0 +1,279 @@
+#[cfg(not(test))]
+use std::time::Instant;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_rt::time::{sleep, timeout};
+use actix_web::web::Data;
+use log::{debug, info, warn};
+#[cfg(test)]
+use mock_instant::Instant;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::{
+    handlers::search_helpers::{record_failure, record_success},
+    search_params::SearchParams,
+    searx_client::{failure_kind, FailureKind, SearxProvider},
+    AppConfig, Cache,
+};
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct ProbeConfig {
+    pub enabled: bool,
+    /// How often a probe round starts.
+    pub interval_secs: u64,
+    /// Probes in flight at the same time.
+    pub concurrency: usize,
+    /// An instance is probed at most once per this many seconds.
+    pub per_instance_interval_secs: u64,
+    pub timeout_ms: u64,
+    pub query: String,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 60,
+            concurrency: 2,
+            per_instance_interval_secs: 30 * 60,
+            timeout_ms: 5000,
+            query: "wikipedia".to_string(),
+        }
+    }
+}
+
+/// Instances of the pool whose probe budget allows another probe.
+fn due_instances(cache: &Cache, config: &ProbeConfig) -> Vec<String> {
+    let budget = Duration::from_secs(config.per_instance_interval_secs);
+    cache
+        .instances
+        .iter()
+        .filter(|url| {
+            cache
+                .probed_at
+                .get(*url)
+                .is_none_or(|probed_at| probed_at.elapsed() >= budget)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Probes every due instance with a small search of the HTML page, the one users get,
+/// and feeds the outcome into its health so broken instances trip their breaker before
+/// a user is sent there. Only outages count against an instance, a 403 or 429 turning
+/// rsearx away says nothing about whether the instance serves its search page.
+/// Returns how many instances were probed.
+pub async fn probe_round(
+    cache: &Data<Mutex<Cache>>,
+    client: &Data<Arc<dyn SearxProvider>>,
+    app_config: &Data<Mutex<AppConfig>>,
+) -> usize {
+    let (config, breaker) = {
+        let app_conf_guard = app_config.lock().unwrap();
+        (
+            app_conf_guard.probe.clone().unwrap_or_default(),
+            app_conf_guard.breaker.clone().unwrap_or_default(),
+        )
+    };
+    let due = {
+        let mut cache_guard = cache.lock().unwrap();
+        let due = due_instances(&cache_guard, &config);
+        for url in &due {
+            cache_guard.probed_at.insert(url.clone(), Instant::now());
+        }
+        due
+    };
+    let semaphore = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    let mut handles = Vec::new();
+    for url in &due {
+        let permit = match semaphore.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+        let client = client.get_ref().clone();
+        let query = SearchParams::new(&config.query);
+        let url = url.clone();
+        let probe_timeout = Duration::from_millis(config.timeout_ms);
+        handles.push(actix_rt::spawn(async move {
+            let _permit = permit;
+            let started = Instant::now();
+            let result = timeout(probe_timeout, client.get_instance_search_body(&url, &query))
+                .await
+                .map_err(|_| anyhow::anyhow!("probe timed out"))
+                .and_then(|result| result);
+            (url, result.map(|_| started.elapsed()))
+        }));
+    }
+    for handle in handles {
+        match handle.await {
+            Ok((url, Ok(latency))) => {
+                debug!("probe of {url} took {}ms", latency.as_millis());
+                record_success(cache, &url, Some(latency));
+            }
+            Ok((url, Err(err))) if failure_kind(&err) == FailureKind::Outage => {
+                warn!("probe of {url} failed: {err}");
+                record_failure(cache, &url, format!("probe failed: {err}"), &breaker);
+            }
+            Ok((url, Err(err))) => debug!("probe of {url} was inconclusive: {err}"),
+            Err(err) => warn!("probe task failed: {err}"),
+        }
+    }
+    due.len()
+}
+
+pub async fn probe_periodically(
+    cache: Data<Mutex<Cache>>,
+    client: Data<Arc<dyn SearxProvider>>,
+    app_config: Data<Mutex<AppConfig>>,
+) {
+    loop {
+        let interval_secs = app_config
+            .lock()
+            .unwrap()
+            .probe
+            .clone()
+            .unwrap_or_default()
+            .interval_secs;
+        sleep(Duration::from_secs(interval_secs)).await;
+        let probed = probe_round(&cache, &client, &app_config).await;
+        if probed > 0 {
+            info!("probed {probed} instances");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use mock_instant::MockClock;
+
+    use super::*;
+    use crate::{
+        health::{BreakerConfig, BreakerState},
+        searx_client::{tests::status_error, MockSearxProvider},
+        HOUR,
+    };
+
+    #[actix_rt::test]
+    async fn probe_round_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_get_instance_search_body()
+            .times(2)
+            .returning(|url, _| match url {
+                "https://up.org/" => Ok("<html></html>".to_string()),
+                _ => Err(anyhow!("connection refused")),
+            });
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_config = AppConfig {
+            breaker: Some(BreakerConfig {
+                failure_threshold: 1,
+                ..BreakerConfig::default()
+            }),
+            probe: Some(ProbeConfig {
+                enabled: true,
+                concurrency: 1,
+                ..ProbeConfig::default()
+            }),
+            ..AppConfig::default()
+        };
+        let app_config = Data::new(Mutex::new(app_config));
+        let cache = Cache::new(
+            vec![
+                "https://up.org/".to_string(),
+                "https://down.org/".to_string(),
+            ],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
+        let cache = Data::new(Mutex::new(cache));
+
+        assert_eq!(probe_round(&cache, &client_mock, &app_config).await, 2);
+        {
+            let cache_guard = cache.lock().unwrap();
+            let breaker = BreakerConfig::default();
+            assert_eq!(
+                cache_guard.health["https://up.org/"].success_rate,
+                Some(1.0)
+            );
+            assert!(cache_guard.health["https://up.org/"].latency.is_some());
+            assert_eq!(
+                cache_guard.health["https://down.org/"].state(&breaker),
+                BreakerState::Open
+            );
+        }
+        // the budget allows no second probe yet
+        assert_eq!(probe_round(&cache, &client_mock, &app_config).await, 0);
+        MockClock::advance(Duration::from_secs(10 * 60));
+        assert_eq!(probe_round(&cache, &client_mock, &app_config).await, 0);
+    }
+
+    #[actix_rt::test]
+    async fn probe_round_ignores_refusals_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_get_instance_search_body()
+            .returning(|url, _| match url {
+                "https://forbidden.org/" => Err(status_error(403)),
+                _ => Err(status_error(503)),
+            });
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_config = AppConfig {
+            breaker: Some(BreakerConfig {
+                failure_threshold: 1,
+                ..BreakerConfig::default()
+            }),
+            ..AppConfig::default()
+        };
+        let app_config = Data::new(Mutex::new(app_config));
+        let cache = Cache::new(
+            vec![
+                "https://forbidden.org/".to_string(),
+                "https://broken.org/".to_string(),
+            ],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
+        let cache = Data::new(Mutex::new(cache));
+
+        assert_eq!(probe_round(&cache, &client_mock, &app_config).await, 2);
+        let cache_guard = cache.lock().unwrap();
+        let breaker = BreakerConfig::default();
+        let state = |url: &str| {
+            cache_guard
+                .health
+                .get(url)
+                .cloned()
+                .unwrap_or_default()
+                .state(&breaker)
+        };
+        assert_eq!(state("https://forbidden.org/"), BreakerState::Closed);
+        assert_eq!(state("https://broken.org/"), BreakerState::Open);
+    }
+
+    #[test]
+    fn probe_config_defaults_test() {
+        let config: ProbeConfig = serde_json::from_str(r#"{"enabled": true}"#).unwrap();
+        assert!(config.enabled);
+        assert_eq!(config.timeout_ms, ProbeConfig::default().timeout_ms);
+    }
+
+    #[test]
+    fn due_instances_test() {
+        let config = ProbeConfig::default();
+        let mut cache = Cache::new(
+            vec!["a".to_string(), "b".to_string()],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
+        cache.probed_at.insert("a".to_string(), Instant::now());
+        assert_eq!(due_instances(&cache, &config), vec!["b"]);
+        MockClock::advance(Duration::from_secs(config.per_instance_interval_secs));
+        assert_eq!(due_instances(&cache, &config), vec!["a", "b"]);
+    }
+}
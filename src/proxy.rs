@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use log::warn;
+use reqwest::{Client, Proxy, Url};
+use serde::{Deserialize, Serialize};
+
+pub static NORMAL_NETWORK: &str = "normal";
+
+/// Upstream proxies, e.g.
+/// `{"all": "http://proxy.corp:3128", "networks": {"tor": "socks5h://127.0.0.1:9050"}}`.
+/// Use `socks5h` for Tor so `.onion` names are resolved by the proxy.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    /// Used for every request without a network specific proxy, including searx.space.
+    pub all: Option<String>,
+    /// Proxy per searx.space `network_type`: `normal`, `tor` or `i2p`.
+    #[serde(default)]
+    pub networks: HashMap<String, String>,
+}
+
+impl ProxyConfig {
+    /// Whether instances of `network` can be reached with this configuration.
+    pub fn supports(&self, network: &str) -> bool {
+        network == NORMAL_NETWORK || self.networks.contains_key(network)
+    }
+
+    /// `networks` without the ones that have no proxy configured.
+    pub fn reachable(&self, networks: Vec<String>) -> Vec<String> {
+        networks
+            .into_iter()
+            .filter(|network| {
+                let supported = self.supports(network);
+                if !supported {
+                    warn!("ignoring {network} instances, no proxy configured for them");
+                }
+                supported
+            })
+            .collect()
+    }
+
+    pub fn build_clients(&self) -> anyhow::Result<(Client, HashMap<String, Client>)> {
+        let default_client = build_client(self.all.as_deref())?;
+        let clients = self
+            .networks
+            .iter()
+            .map(|(network, proxy)| Ok((network.clone(), build_client(Some(proxy))?)))
+            .collect::<anyhow::Result<HashMap<String, Client>>>()?;
+        Ok((default_client, clients))
+    }
+}
+
+fn build_client(proxy: Option<&str>) -> anyhow::Result<Client> {
+    let builder = Client::builder();
+    let builder = match proxy {
+        Some(proxy) => builder.proxy(Proxy::all(proxy)?),
+        None => builder,
+    };
+    Ok(builder.build()?)
+}
+
+/// Network an instance lives on, judged by its host the same way searx.space does.
+pub fn network_type(instance_url: &str) -> &'static str {
+    let host = Url::parse(instance_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+        .unwrap_or_default();
+    if host.ends_with(".onion") {
+        "tor"
+    } else if host.ends_with(".i2p") {
+        "i2p"
+    } else {
+        NORMAL_NETWORK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_type_test() {
+        assert_eq!(network_type("https://searx.be/"), "normal");
+        assert_eq!(
+            network_type("http://searxspbitokayvkhzhsnljde7rqmn7rvoga6e4waeub3h7ug3nghoad.onion/"),
+            "tor"
+        );
+        assert_eq!(network_type("http://search.i2p/"), "i2p");
+        assert_eq!(network_type("not a url"), "normal");
+    }
+
+    #[test]
+    fn reachable_test() {
+        let config = ProxyConfig {
+            all: None,
+            networks: HashMap::from([("tor".to_string(), "socks5h://127.0.0.1:9050".to_string())]),
+        };
+        let networks = vec!["normal".to_string(), "tor".to_string(), "i2p".to_string()];
+        assert_eq!(
+            config.reachable(networks),
+            vec!["normal".to_string(), "tor".to_string()]
+        );
+        assert!(config.build_clients().is_ok());
+
+        let config = ProxyConfig {
+            all: Some("not a proxy".to_string()),
+            ..ProxyConfig::default()
+        };
+        assert!(config.build_clients().is_err());
+    }
+}
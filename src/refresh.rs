@@ -0,0 +1,136 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_rt::time::sleep;
+use actix_web::web::Data;
+use log::{info, warn};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    handlers::search_helpers::{refresh_instances, ttl_exceeded},
+    searx_client::SearxProvider,
+    AppConfig, Cache, HOUR,
+};
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct RefreshConfig {
+    pub interval_secs: u64,
+    /// Up to this much is added to every interval, so restarted instances don't
+    /// all hit the sources at the same moment.
+    pub jitter_secs: u64,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: HOUR.into(),
+            jitter_secs: 5 * 60,
+        }
+    }
+}
+
+/// Time until the pool expires plus a random jitter.
+pub fn refresh_delay(cache: &Cache, config: &RefreshConfig, rng: &mut impl Rng) -> Duration {
+    let remaining = cache.ttl.saturating_sub(cache.creation_time.elapsed());
+    remaining + Duration::from_secs(rng.gen_range(0..=config.jitter_secs))
+}
+
+/// Keeps the pool fresh in the background, so searches never wait for the sources
+/// unless the pool is empty. Searches keep using the previous pool meanwhile.
+pub async fn refresh_periodically(
+    cache: Data<Mutex<Cache>>,
+    client: Data<Arc<dyn SearxProvider>>,
+    app_config: Data<Mutex<AppConfig>>,
+) {
+    loop {
+        let config = app_config
+            .lock()
+            .unwrap()
+            .refresh
+            .clone()
+            .unwrap_or_default();
+        let delay = refresh_delay(&cache.lock().unwrap(), &config, &mut thread_rng());
+        info!("next instance refresh in {}s", delay.as_secs());
+        sleep(delay).await;
+        refresh_if_due(&cache, &client, &app_config).await;
+    }
+}
+
+/// The pool may have been refreshed meanwhile, e.g. by a cold search or by saving a filter.
+async fn refresh_if_due(
+    cache: &Data<Mutex<Cache>>,
+    client: &Data<Arc<dyn SearxProvider>>,
+    app_config: &Data<Mutex<AppConfig>>,
+) -> bool {
+    let is_due = ttl_exceeded(&cache.lock().unwrap());
+    if !is_due {
+        return false;
+    }
+    if let Err(err) = refresh_instances(cache, client, app_config).await {
+        warn!("refreshing instances failed: {err}");
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use mock_instant::{Instant, MockClock};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::searx_client::MockSearxProvider;
+
+    #[test]
+    fn refresh_config_defaults_test() {
+        let config: RefreshConfig = serde_json::from_str(r#"{"interval_secs": 600}"#).unwrap();
+        assert_eq!(config.interval_secs, 600);
+        assert_eq!(config.jitter_secs, RefreshConfig::default().jitter_secs);
+    }
+
+    #[test]
+    fn refresh_delay_test() {
+        let config = RefreshConfig {
+            interval_secs: 100,
+            jitter_secs: 10,
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let cache = Cache::new(Vec::new(), Instant::now(), Duration::from_secs(100));
+        MockClock::advance(Duration::from_secs(40));
+        for _ in 0..20 {
+            let delay = refresh_delay(&cache, &config, &mut rng);
+            assert!(delay >= Duration::from_secs(60) && delay <= Duration::from_secs(70));
+        }
+        MockClock::advance(Duration::from_secs(500));
+        assert!(refresh_delay(&cache, &config, &mut rng) <= Duration::from_secs(10));
+    }
+
+    #[actix_rt::test]
+    async fn refresh_if_due_test() {
+        let mut client_mock = MockSearxProvider::new();
+        client_mock
+            .expect_fetch_instances()
+            .times(1)
+            .returning(|_| Err(anyhow!("searx.space unreachable")));
+        let client_mock = Data::new(Arc::new(client_mock) as Arc<dyn SearxProvider>);
+        let app_conf = Data::new(Mutex::new(AppConfig::default()));
+        let cache = Cache::new(
+            vec!["1".to_string()],
+            Instant::now(),
+            Duration::from_secs(HOUR.into()),
+        );
+        let cache = Data::new(Mutex::new(cache));
+        assert!(!refresh_if_due(&cache, &client_mock, &app_conf).await);
+
+        MockClock::advance(Duration::from_secs(HOUR.into()));
+        assert!(refresh_if_due(&cache, &client_mock, &app_conf).await);
+        let cache_guard = cache.lock().unwrap();
+        assert_eq!(cache_guard.instances, vec!["1".to_string()]);
+        assert!(cache_guard.stale);
+        assert!(!ttl_exceeded(&cache_guard));
+    }
+}
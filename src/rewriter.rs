@@ -0,0 +1,804 @@
+use std::collections::HashMap;
+
+use actix_web::web::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// Attributes whose value is a single URL.
+static URL_ATTRIBUTES: [&str; 9] = [
+    "href",
+    "src",
+    "action",
+    "formaction",
+    "poster",
+    "data",
+    "cite",
+    "background",
+    "manifest",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlPolicy {
+    /// Resolve against the instance so the browser loads it from the instance.
+    Absolute,
+    /// Turn URLs pointing at the instance's home or search page into rsearx-relative ones,
+    /// e.g. `/search?q=..`. Other URLs, which rsearx doesn't serve, are made absolute.
+    Rsearx,
+    /// Leave the value as the instance sent it.
+    Keep,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RewriteConfig {
+    pub default: UrlPolicy,
+    /// Overrides per attribute name, e.g. `"action": "rsearx"`. `style` covers both
+    /// `style` attributes and `<style>` elements, `srcset` covers `srcset` and `imagesrcset`.
+    pub attributes: HashMap<String, UrlPolicy>,
+    /// Point the instance's search form, pagination and category links at rsearx's
+    /// own `/search`, so follow-up queries are balanced again.
+    pub route_searches: bool,
+}
+
+impl Default for RewriteConfig {
+    fn default() -> Self {
+        Self {
+            default: UrlPolicy::Absolute,
+            attributes: HashMap::new(),
+            route_searches: false,
+        }
+    }
+}
+
+impl RewriteConfig {
+    pub fn policy(&self, attribute: &str) -> UrlPolicy {
+        self.attributes
+            .get(attribute)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Streaming rewriter of the URLs in an instance page. Input may arrive in chunks of any
+/// size: a tag, comment or `<script>`/`<style>` element cut by a chunk boundary is held
+/// back until it is complete, text between tags is passed on as it comes and `<script>`
+/// bodies are never parsed.
+pub struct HtmlRewriter {
+    instance: Url,
+    base: Url,
+    config: RewriteConfig,
+    sticky_instance: Option<String>,
+    /// Input held back because it ends inside a tag, comment or raw text element.
+    pending: String,
+    /// Leading bytes of a UTF-8 sequence completed by the next chunk.
+    undecoded: Vec<u8>,
+    /// `script` or `style` element whose closing tag hasn't arrived yet.
+    raw_text: Option<String>,
+}
+
+struct Attribute {
+    name: String,
+    value: Option<(usize, usize)>,
+}
+
+impl HtmlRewriter {
+    pub fn new(instance_url: &str, config: RewriteConfig) -> anyhow::Result<Self> {
+        let instance = Url::parse(instance_url)?;
+        Ok(Self {
+            base: instance.clone(),
+            instance,
+            config,
+            sticky_instance: None,
+            pending: String::new(),
+            undecoded: Vec::new(),
+            raw_text: None,
+        })
+    }
+
+    /// Routed searches carry `instance` and `sticky` so rsearx keeps using `instance_url`.
+    pub fn sticky_to(mut self, instance_url: Option<String>) -> Self {
+        self.sticky_instance = instance_url;
+        self
+    }
+
+    /// Rewrites a response body chunk by chunk as it streams in. The body isn't polled
+    /// again once it has ended.
+    pub fn rewrite_stream<S, E>(self, body: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+    {
+        let body = body.fuse();
+        stream::unfold((body, Some(self)), |(mut body, mut rewriter)| async move {
+            loop {
+                let output = match body.next().await {
+                    Some(Ok(chunk)) => rewriter.as_mut()?.write_bytes(&chunk),
+                    Some(Err(err)) => return Some((Err(err), (body, rewriter))),
+                    None => rewriter.take()?.finish(),
+                };
+                if !output.is_empty() {
+                    return Some((Ok(Bytes::from(output)), (body, rewriter)));
+                }
+            }
+        })
+    }
+
+    /// Rewritten output for the next chunk of the page, as far as it is complete.
+    pub fn write(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        self.process(false)
+    }
+
+    /// Like `write`, for raw bytes of a UTF-8 page. Invalid sequences are replaced.
+    pub fn write_bytes(&mut self, chunk: &[u8]) -> String {
+        self.undecoded.extend_from_slice(chunk);
+        let decodable = match std::str::from_utf8(&self.undecoded) {
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            _ => self.undecoded.len(),
+        };
+        let text = String::from_utf8_lossy(&self.undecoded[..decodable]).into_owned();
+        self.undecoded.drain(..decodable);
+        self.write(&text)
+    }
+
+    /// Flushes the held back input, unterminated tags and elements are passed on as they are.
+    pub fn finish(mut self) -> String {
+        let undecoded = std::mem::take(&mut self.undecoded);
+        self.pending.push_str(&String::from_utf8_lossy(&undecoded));
+        self.process(true)
+    }
+
+    /// Rewrites `pending` up to the first incomplete construct, which stays pending
+    /// unless this is the `last` call.
+    fn process(&mut self, last: bool) -> String {
+        let pending = std::mem::take(&mut self.pending);
+        let mut output = String::with_capacity(pending.len() + pending.len() / 8);
+        let mut rest = pending.as_str();
+        loop {
+            if let Some(name) = self.raw_text.take() {
+                let close = match find_closing_tag(rest, &name) {
+                    Some(close) => close,
+                    None if last => rest.len(),
+                    None => {
+                        self.raw_text = Some(name);
+                        break;
+                    }
+                };
+                if name == "style" {
+                    let policy = self.config.policy("style");
+                    output.push_str(&self.rewrite_css(&rest[..close], policy));
+                } else {
+                    output.push_str(&rest[..close]);
+                }
+                rest = &rest[close..];
+            }
+            let start = match rest.find('<') {
+                Some(start) => start,
+                None => {
+                    output.push_str(rest);
+                    rest = "";
+                    break;
+                }
+            };
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            // too short to tell a comment or tag from a lone `<`
+            if !last && rest.len() < 4 && "<!--".starts_with(rest) {
+                break;
+            }
+            if rest.starts_with("<!--") {
+                let end = match rest.find("-->") {
+                    Some(end) => end + 3,
+                    None if last => rest.len(),
+                    None => break,
+                };
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            let is_start_tag = rest
+                .as_bytes()
+                .get(1)
+                .map(|byte| byte.is_ascii_alphabetic())
+                .unwrap_or_default();
+            if !is_start_tag {
+                let end =
+                    if rest.starts_with("</") || rest.starts_with("<!") || rest.starts_with("<?") {
+                        match rest.find('>') {
+                            Some(end) => end + 1,
+                            None if last => rest.len(),
+                            None => break,
+                        }
+                    } else {
+                        1
+                    };
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            let end = match find_tag_end(rest) {
+                Some(end) => end,
+                None if last => rest.len(),
+                None => break,
+            };
+            let tag = &rest[..end];
+            rest = &rest[end..];
+            let name = self.rewrite_tag(tag, &mut output);
+            if name == "script" || name == "style" {
+                self.raw_text = Some(name);
+            }
+        }
+        self.pending = rest.to_string();
+        output
+    }
+
+    /// Writes the (possibly rewritten) tag to `output` and returns its lowercase name.
+    fn rewrite_tag(&mut self, tag: &str, output: &mut String) -> String {
+        let (name, attributes) = parse_tag(tag);
+        if name == "base" {
+            if let Some(href) = attributes.iter().find(|attribute| attribute.name == "href") {
+                if let Some((start, end)) = href.value {
+                    if let Ok(base) = self.base.join(tag[start..end].trim()) {
+                        self.base = base;
+                    }
+                }
+            }
+        }
+        let is_routed_form = name == "form"
+            && attributes.iter().any(|attribute| {
+                attribute.name == "action"
+                    && attribute
+                        .value
+                        .and_then(|(start, end)| self.route_search(&tag[start..end]))
+                        .is_some()
+            });
+        let mut last = 0;
+        for attribute in &attributes {
+            let (start, end) = match attribute.value {
+                Some(span) => span,
+                None => continue,
+            };
+            let value = &tag[start..end];
+            let routed = match attribute.name.as_str() {
+                "href" | "action" | "formaction" => self.route_search(value),
+                _ => None,
+            };
+            let rewritten = if let Some(routed) = routed {
+                routed
+            } else if is_routed_form && attribute.name == "method" {
+                "get".to_string()
+            } else if URL_ATTRIBUTES.contains(&attribute.name.as_str()) {
+                let policy = if name == "base" {
+                    UrlPolicy::Absolute
+                } else {
+                    self.config.policy(&attribute.name)
+                };
+                self.rewrite_url(value, policy)
+            } else if attribute.name == "srcset" || attribute.name == "imagesrcset" {
+                self.rewrite_srcset(value, self.config.policy("srcset"))
+            } else if attribute.name == "style" {
+                self.rewrite_css(value, self.config.policy("style"))
+            } else {
+                continue;
+            };
+            if rewritten == value {
+                continue;
+            }
+            output.push_str(&tag[last..start]);
+            match tag.as_bytes()[start - 1] {
+                quote @ (b'"' | b'\'') => {
+                    output.push_str(&escape_attribute(&rewritten, quote as char))
+                }
+                _ => {
+                    output.push('"');
+                    output.push_str(&escape_attribute(&rewritten, '"'));
+                    output.push('"');
+                }
+            }
+            last = end;
+        }
+        output.push_str(&tag[last..]);
+        if is_routed_form {
+            if let Some(instance) = &self.sticky_instance {
+                output.push_str(&format!(
+                    r#"<input type="hidden" name="instance" value="{}"><input type="hidden" name="sticky" value="true">"#,
+                    escape_attribute(&instance.replace('&', "&amp;"), '"')
+                ));
+            }
+        }
+        name
+    }
+
+    /// rsearx `/search` URL for values that point at the instance's search endpoint.
+    fn route_search(&self, value: &str) -> Option<String> {
+        if !self.config.route_searches {
+            return None;
+        }
+        let resolved = self.base.join(&decode_entities(value.trim())).ok()?;
+        let relative = self.instance_relative(&resolved)?;
+        let path = relative.split(['?', '#']).next().unwrap_or_default();
+        if path.trim_end_matches('/') != "/search" {
+            return None;
+        }
+        let mut routed = Url::parse("http://rsearx/search").unwrap();
+        {
+            let mut pairs = routed.query_pairs_mut();
+            resolved
+                .query_pairs()
+                .filter(|(key, _)| key != "instance" && key != "sticky")
+                .for_each(|(key, value)| {
+                    pairs.append_pair(&key, &value);
+                });
+            if let Some(instance) = &self.sticky_instance {
+                pairs.append_pair("instance", instance);
+                pairs.append_pair("sticky", "true");
+            }
+        }
+        let routed = match routed.query() {
+            Some(query) if !query.is_empty() => format!("/search?{query}"),
+            _ => "/search".to_string(),
+        };
+        Some(routed.replace('&', "&amp;"))
+    }
+
+    fn rewrite_url(&self, value: &str, policy: UrlPolicy) -> String {
+        let trimmed = value.trim();
+        if policy == UrlPolicy::Keep || trimmed.is_empty() || trimmed.starts_with('#') {
+            return value.to_string();
+        }
+        let resolved = match self.base.join(trimmed) {
+            Ok(resolved) if matches!(resolved.scheme(), "http" | "https") => resolved,
+            _ => return value.to_string(),
+        };
+        if policy == UrlPolicy::Rsearx {
+            if let Some(relative) = self
+                .instance_relative(&resolved)
+                .filter(|relative| served_by_rsearx(relative))
+            {
+                return relative;
+            }
+        }
+        resolved.to_string()
+    }
+
+    /// `/path?query#fragment` relative to the instance root when `url` points into the instance.
+    fn instance_relative(&self, url: &Url) -> Option<String> {
+        if url.origin() != self.instance.origin() {
+            return None;
+        }
+        let instance_path = self.instance.path().trim_end_matches('/');
+        let path = url.path().strip_prefix(instance_path)?;
+        if !path.is_empty() && !path.starts_with('/') {
+            return None;
+        }
+        let mut relative = format!("/{}", path.trim_start_matches('/'));
+        if let Some(query) = url.query() {
+            relative.push('?');
+            relative.push_str(query);
+        }
+        if let Some(fragment) = url.fragment() {
+            relative.push('#');
+            relative.push_str(fragment);
+        }
+        Some(relative)
+    }
+
+    fn rewrite_srcset(&self, value: &str, policy: UrlPolicy) -> String {
+        value
+            .split(',')
+            .map(|candidate| {
+                let candidate = candidate.trim();
+                match candidate.split_once(char::is_whitespace) {
+                    Some((url, descriptor)) => {
+                        format!("{} {}", self.rewrite_url(url, policy), descriptor.trim())
+                    }
+                    None => self.rewrite_url(candidate, policy),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Rewrites `url(...)` and `@import "..."` references in a style sheet or inline style.
+    fn rewrite_css(&self, css: &str, policy: UrlPolicy) -> String {
+        if policy == UrlPolicy::Keep {
+            return css.to_string();
+        }
+        let lower = css.to_ascii_lowercase();
+        let mut output = String::with_capacity(css.len());
+        let mut cursor = 0;
+        loop {
+            let next_url = lower[cursor..].find("url(").map(|start| (start, 4, true));
+            let next_import = lower[cursor..]
+                .find("@import")
+                .map(|start| (start, 7, false));
+            let (start, keyword_len, is_url) = match (next_url, next_import) {
+                (Some(url), Some(import)) if url.0 < import.0 => url,
+                (_, Some(import)) => import,
+                (Some(url), None) => url,
+                (None, None) => break,
+            };
+            let mut value_start = cursor + start + keyword_len;
+            value_start += css[value_start..].len() - css[value_start..].trim_start().len();
+            output.push_str(&css[cursor..value_start]);
+            cursor = value_start;
+            let quote = css[cursor..]
+                .chars()
+                .next()
+                .filter(|char| *char == '"' || *char == '\'');
+            let (value, value_end) = match quote {
+                Some(quote) => {
+                    let end = css[cursor + 1..]
+                        .find(quote)
+                        .map(|end| cursor + 1 + end)
+                        .unwrap_or(css.len());
+                    (&css[cursor + 1..end], (end + 1).min(css.len()))
+                }
+                None if is_url => {
+                    let end = css[cursor..]
+                        .find(')')
+                        .map(|end| cursor + end)
+                        .unwrap_or(css.len());
+                    (css[cursor..end].trim_end(), end)
+                }
+                // `@import url(...)`, the url is picked up by the next iteration
+                None => continue,
+            };
+            let rewritten = self.rewrite_url(value, policy);
+            match quote {
+                Some(quote) => {
+                    output.push(quote);
+                    output.push_str(&rewritten);
+                    output.push(quote);
+                }
+                None => output.push_str(&rewritten),
+            }
+            cursor = value_end;
+        }
+        output.push_str(&css[cursor..]);
+        output
+    }
+}
+
+/// Index just past the `>` closing the tag, quotes are respected.
+fn find_tag_end(tag: &str) -> Option<usize> {
+    let mut quote: Option<u8> = None;
+    for (index, byte) in tag.bytes().enumerate() {
+        match quote {
+            Some(open) if byte == open => quote = None,
+            Some(_) => {}
+            None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+            None if byte == b'>' => return Some(index + 1),
+            None => {}
+        }
+    }
+    None
+}
+
+fn find_closing_tag(html: &str, name: &str) -> Option<usize> {
+    let needle = format!("</{name}");
+    html.to_ascii_lowercase().find(&needle)
+}
+
+/// Lowercase tag name and attributes with the byte span of their value inside `tag`.
+fn parse_tag(tag: &str) -> (String, Vec<Attribute>) {
+    let bytes = tag.as_bytes();
+    let mut index = 1;
+    while index < bytes.len() && !is_name_end(bytes[index]) {
+        index += 1;
+    }
+    let name = tag[1..index].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    loop {
+        while index < bytes.len() && (bytes[index].is_ascii_whitespace() || bytes[index] == b'/') {
+            index += 1;
+        }
+        if index >= bytes.len() || bytes[index] == b'>' {
+            break;
+        }
+        let name_start = index;
+        while index < bytes.len() && !is_name_end(bytes[index]) && bytes[index] != b'=' {
+            index += 1;
+        }
+        let attribute_name = tag[name_start..index].to_ascii_lowercase();
+        while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+            index += 1;
+        }
+        if index >= bytes.len() || bytes[index] != b'=' {
+            attributes.push(Attribute {
+                name: attribute_name,
+                value: None,
+            });
+            continue;
+        }
+        index += 1;
+        while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+            index += 1;
+        }
+        let value = match bytes.get(index) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let start = index + 1;
+                let end = tag[start..]
+                    .find(quote as char)
+                    .map(|end| start + end)
+                    .unwrap_or(bytes.len());
+                index = (end + 1).min(bytes.len());
+                (start, end)
+            }
+            _ => {
+                let start = index;
+                while index < bytes.len()
+                    && !bytes[index].is_ascii_whitespace()
+                    && bytes[index] != b'>'
+                {
+                    index += 1;
+                }
+                (start, index)
+            }
+        };
+        attributes.push(Attribute {
+            name: attribute_name,
+            value: Some(value),
+        });
+    }
+    (name, attributes)
+}
+
+fn is_name_end(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || byte == b'>' || byte == b'/'
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Whether rsearx has a route for an instance-relative URL: its home page and `/search`.
+fn served_by_rsearx(relative: &str) -> bool {
+    let path = relative.split(['?', '#']).next().unwrap_or_default();
+    matches!(path.trim_end_matches('/'), "" | "/search")
+}
+
+fn escape_attribute(value: &str, quote: char) -> String {
+    match quote {
+        '\'' => value.replace('\'', "&#39;"),
+        _ => value.replace('"', "&quot;"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite_all(mut rewriter: HtmlRewriter, html: &str) -> String {
+        let mut output = rewriter.write(html);
+        output.push_str(&rewriter.finish());
+        output
+    }
+
+    fn rewrite(html: &str, instance: &str, config: &RewriteConfig) -> String {
+        rewrite_all(HtmlRewriter::new(instance, config.clone()).unwrap(), html)
+    }
+
+    #[test]
+    fn rewrite_absolute_test() {
+        let body =
+            r#"<a href="/lola" /> <img src="/heheszki.jpg" /> <form action="/search"></form> "#;
+        let after = rewrite(body, "https://test.com/", &RewriteConfig::default());
+        assert_eq!(
+            after,
+            r#"<a href="https://test.com/lola" /> <img src="https://test.com/heheszki.jpg" /> <form action="https://test.com/search"></form> "#
+        );
+    }
+
+    #[test]
+    fn rewrite_handles_all_url_kinds_test() {
+        let body = concat!(
+            r#"<p>see href="/not-a-tag"</p>"#,
+            r#"<img srcset="/a.png 1x, img/b.png 2x" src=//cdn.org/c.png>"#,
+            r#"<div style="background: url('/bg.png')"></div>"#,
+            r#"<style>@import "/theme.css"; .x { background:url(/x.svg) }</style>"#,
+            r##"<a href="#top">top</a><a href="javascript:void(0)">js</a>"##,
+            r#"<script>var a = "<a href='/x'>";</script>"#,
+            r#"<!-- <a href="/comment"> -->"#,
+            r#"<a href="about">about</a>"#,
+        );
+        let after = rewrite(body, "https://test.com/searx/", &RewriteConfig::default());
+        assert_eq!(
+            after,
+            concat!(
+                r#"<p>see href="/not-a-tag"</p>"#,
+                r#"<img srcset="https://test.com/a.png 1x, https://test.com/searx/img/b.png 2x" src="https://cdn.org/c.png">"#,
+                r#"<div style="background: url('https://test.com/bg.png')"></div>"#,
+                r#"<style>@import "https://test.com/theme.css"; .x { background:url(https://test.com/x.svg) }</style>"#,
+                r##"<a href="#top">top</a><a href="javascript:void(0)">js</a>"##,
+                r#"<script>var a = "<a href='/x'>";</script>"#,
+                r#"<!-- <a href="/comment"> -->"#,
+                r#"<a href="https://test.com/searx/about">about</a>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn rewrite_in_chunks_test() {
+        let body = concat!(
+            r#"<p>Zażółć <b>x</b> < 3</p><!-- <a href="/comment"> -->"#,
+            r#"<img srcset="/a.png 1x" src=//cdn.org/c.png>"#,
+            r#"<style>.x { background:url(/x.svg) }</style>"#,
+            r#"<script>var a = "<a href='/x'>";</script></div>"#,
+            r#"<a href="about">about</a><"#,
+        );
+        let config = RewriteConfig::default();
+        let whole = rewrite(body, "https://test.com/searx/", &config);
+        for size in 1..body.len() {
+            let mut rewriter =
+                HtmlRewriter::new("https://test.com/searx/", config.clone()).unwrap();
+            let mut output = String::new();
+            for chunk in body.as_bytes().chunks(size) {
+                output.push_str(&rewriter.write_bytes(chunk));
+            }
+            output.push_str(&rewriter.finish());
+            assert_eq!(output, whole, "chunks of {size} bytes");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rewrite_stream_test() {
+        let chunks = [
+            r#"<a hr"#,
+            r#"ef="/x">x</a><scr"#,
+            r#"ipt>"<a href='/y'>"</script>"#,
+        ];
+        let body = stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok::<_, anyhow::Error>(Bytes::from(chunk.to_string()))),
+        );
+        let rewritten: Vec<Bytes> =
+            HtmlRewriter::new("https://test.com/", RewriteConfig::default())
+                .unwrap()
+                .rewrite_stream(body)
+                .map(|chunk| chunk.unwrap())
+                .collect()
+                .await;
+        assert_eq!(
+            rewritten.concat(),
+            br#"<a href="https://test.com/x">x</a><script>"<a href='/y'>"</script>"#
+        );
+    }
+
+    /// A body like the client's, which panics when polled after its end.
+    fn unfold_body(chunks: Vec<&'static str>) -> impl Stream<Item = anyhow::Result<Bytes>> + Unpin {
+        Box::pin(stream::unfold(
+            chunks.into_iter(),
+            |mut chunks| async move { chunks.next().map(|chunk| (Ok(Bytes::from(chunk)), chunks)) },
+        ))
+    }
+
+    #[actix_rt::test]
+    async fn rewrite_stream_stops_polling_ended_body_test() {
+        for (chunks, expected) in [
+            (
+                vec![r#"<a href="/x">x</a>"#],
+                r#"<a href="https://test.com/x">x</a>"#,
+            ),
+            (
+                vec![r#"<a href="/x">x</a><a hr"#],
+                r#"<a href="https://test.com/x">x</a><a hr"#,
+            ),
+        ] {
+            let rewritten: Vec<Bytes> =
+                HtmlRewriter::new("https://test.com/", RewriteConfig::default())
+                    .unwrap()
+                    .rewrite_stream(unfold_body(chunks))
+                    .map(|chunk| chunk.unwrap())
+                    .collect()
+                    .await;
+            assert_eq!(rewritten.concat(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn rewrite_respects_base_test() {
+        let body = r#"<head><base href="/static/"></head><img src="logo.png">"#;
+        let after = rewrite(body, "https://test.com/", &RewriteConfig::default());
+        assert_eq!(
+            after,
+            r#"<head><base href="https://test.com/static/"></head><img src="https://test.com/static/logo.png">"#
+        );
+    }
+
+    #[test]
+    fn rewrite_config_defaults_test() {
+        let config: RewriteConfig = serde_json::from_str(r#"{"route_searches": true}"#).unwrap();
+        assert!(config.route_searches);
+        assert_eq!(config.default, UrlPolicy::Absolute);
+    }
+
+    #[test]
+    fn rewrite_policies_test() {
+        let config = RewriteConfig {
+            default: UrlPolicy::Absolute,
+            attributes: HashMap::from([
+                ("action".to_string(), UrlPolicy::Rsearx),
+                ("src".to_string(), UrlPolicy::Keep),
+            ]),
+            ..RewriteConfig::default()
+        };
+        let body = r#"<form action="/searx/search?q=a&amp;b=c"></form><img src="/x.png"><a href="/searx/about">"#;
+        let after = rewrite(body, "https://test.com/searx/", &config);
+        assert_eq!(
+            after,
+            r#"<form action="/search?q=a&amp;b=c"></form><img src="/x.png"><a href="https://test.com/searx/about">"#
+        );
+
+        let body = r#"<form action="https://other.org/search"></form>"#;
+        let after = rewrite(body, "https://test.com/", &config);
+        assert_eq!(after, r#"<form action="https://other.org/search"></form>"#);
+    }
+
+    #[test]
+    fn rewrite_rsearx_policy_only_for_served_paths_test() {
+        let config = RewriteConfig {
+            default: UrlPolicy::Rsearx,
+            ..RewriteConfig::default()
+        };
+        let body = concat!(
+            r#"<a href="/searx/">Home</a><a href="/searx/search?q=a">Next</a>"#,
+            r#"<a href="/searx/preferences">Preferences</a><link href="/searx/static/x.css">"#,
+        );
+        let after = rewrite(body, "https://test.com/searx/", &config);
+        assert_eq!(
+            after,
+            concat!(
+                r#"<a href="/">Home</a><a href="/search?q=a">Next</a>"#,
+                r#"<a href="https://test.com/searx/preferences">Preferences</a><link href="https://test.com/searx/static/x.css">"#,
+            )
+        );
+    }
+
+    #[test]
+    fn route_searches_test() {
+        let config = RewriteConfig {
+            route_searches: true,
+            ..RewriteConfig::default()
+        };
+        let body = concat!(
+            r#"<form id="search" method="POST" action="/searx/search"><input name="q"></form>"#,
+            r#"<a href="/searx/search?q=rust&amp;categories=images">Images</a>"#,
+            r#"<a href="/searx/preferences">Preferences</a>"#,
+            r#"<a href="https://other.org/search?q=rust">Other</a>"#,
+        );
+        let after = rewrite(body, "https://test.com/searx/", &config);
+        assert_eq!(
+            after,
+            concat!(
+                r#"<form id="search" method="get" action="/search"><input name="q"></form>"#,
+                r#"<a href="/search?q=rust&amp;categories=images">Images</a>"#,
+                r#"<a href="https://test.com/searx/preferences">Preferences</a>"#,
+                r#"<a href="https://other.org/search?q=rust">Other</a>"#,
+            )
+        );
+
+        let rewriter = HtmlRewriter::new("https://test.com/", config)
+            .unwrap()
+            .sticky_to(Some("https://test.com/".to_string()));
+        let after = rewrite_all(
+            rewriter,
+            r#"<form method="post" action="/search"></form><a href="/search?q=a&amp;pageno=2&amp;instance=x">2</a>"#,
+        );
+        assert_eq!(
+            after,
+            concat!(
+                r#"<form method="get" action="/search?instance=https%3A%2F%2Ftest.com%2F&amp;sticky=true">"#,
+                r#"<input type="hidden" name="instance" value="https://test.com/"><input type="hidden" name="sticky" value="true"></form>"#,
+                r#"<a href="/search?q=a&amp;pageno=2&amp;instance=https%3A%2F%2Ftest.com%2F&amp;sticky=true">2</a>"#,
+            )
+        );
+    }
+}
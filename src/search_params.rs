@@ -0,0 +1,227 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+static TIME_RANGES: [&str; 4] = ["day", "week", "month", "year"];
+static FORMATS: [&str; 4] = ["html", "json", "csv", "rss"];
+
+/// Validated parameters forwarded to an instance's `/search` endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub pageno: Option<u32>,
+    pub categories: Option<String>,
+    pub language: Option<String>,
+    pub time_range: Option<String>,
+    pub safesearch: Option<u8>,
+    pub engines: Option<String>,
+    pub format: Option<String>,
+    /// The user's `Accept-Language`, sent as a header rather than a parameter.
+    #[serde(skip)]
+    pub accept_language: Option<String>,
+}
+
+/// Values used for parameters the request leaves out.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SearchDefaults {
+    pub pageno: Option<u32>,
+    pub categories: Option<String>,
+    pub language: Option<String>,
+    pub time_range: Option<String>,
+    pub safesearch: Option<u8>,
+    pub engines: Option<String>,
+    pub format: Option<String>,
+}
+
+impl SearchParams {
+    pub fn new(q: &str) -> Self {
+        Self {
+            q: q.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_defaults(mut self, defaults: &SearchDefaults) -> Self {
+        self.pageno = self.pageno.or(defaults.pageno);
+        self.categories = self.categories.or_else(|| defaults.categories.clone());
+        self.language = self.language.or_else(|| defaults.language.clone());
+        self.time_range = self.time_range.or_else(|| defaults.time_range.clone());
+        self.safesearch = self.safesearch.or(defaults.safesearch);
+        self.engines = self.engines.or_else(|| defaults.engines.clone());
+        self.format = self.format.or_else(|| defaults.format.clone());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pageno == Some(0) {
+            return Err("pageno has to be greater than 0".to_string());
+        }
+        if let Some(time_range) = &self.time_range {
+            if !time_range.is_empty() && !TIME_RANGES.contains(&time_range.as_str()) {
+                return Err(format!(
+                    "time_range has to be one of {TIME_RANGES:?}, got {time_range:?}"
+                ));
+            }
+        }
+        if let Some(safesearch) = self.safesearch {
+            if safesearch > 2 {
+                return Err(format!("safesearch has to be 0, 1 or 2, got {safesearch}"));
+            }
+        }
+        if let Some(format) = &self.format {
+            if !FORMATS.contains(&format.as_str()) {
+                return Err(format!(
+                    "format has to be one of {FORMATS:?}, got {format:?}"
+                ));
+            }
+        }
+        if let Some(language) = &self.language {
+            let is_valid = !language.is_empty()
+                && language
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_');
+            if !is_valid {
+                return Err(format!("invalid language {language:?}"));
+            }
+        }
+        validate_list("categories", &self.categories)?;
+        validate_list("engines", &self.engines)?;
+        Ok(())
+    }
+
+    pub fn is_html(&self) -> bool {
+        matches!(self.format.as_deref(), None | Some("html"))
+    }
+
+    pub fn append_to(&self, url: &mut Url) {
+        let mut pairs = url.query_pairs_mut();
+        pairs.append_pair("q", &self.q);
+        if let Some(pageno) = self.pageno {
+            pairs.append_pair("pageno", &pageno.to_string());
+        }
+        if let Some(categories) = &self.categories {
+            pairs.append_pair("categories", categories);
+        }
+        if let Some(language) = &self.language {
+            pairs.append_pair("language", language);
+        }
+        if let Some(time_range) = &self.time_range {
+            pairs.append_pair("time_range", time_range);
+        }
+        if let Some(safesearch) = self.safesearch {
+            pairs.append_pair("safesearch", &safesearch.to_string());
+        }
+        if let Some(engines) = &self.engines {
+            pairs.append_pair("engines", engines);
+        }
+        if let Some(format) = &self.format {
+            pairs.append_pair("format", format);
+        }
+    }
+}
+
+/// Comma separated names such as `general,images` or `google images`.
+fn validate_list(name: &str, list: &Option<String>) -> Result<(), String> {
+    let list = match list {
+        Some(list) => list,
+        None => return Ok(()),
+    };
+    let is_valid = list.split(',').all(|item| {
+        let item = item.trim();
+        !item.is_empty()
+            && item
+                .chars()
+                .all(|char| char.is_alphanumeric() || " _-.".contains(char))
+    });
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!("invalid {name} {list:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_defaults_test() {
+        let defaults = SearchDefaults {
+            language: Some("en-US".to_string()),
+            safesearch: Some(1),
+            categories: Some("general".to_string()),
+            ..SearchDefaults::default()
+        };
+        let params = SearchParams {
+            categories: Some("images".to_string()),
+            ..SearchParams::new("rust")
+        }
+        .with_defaults(&defaults);
+        assert_eq!(params.language.as_deref(), Some("en-US"));
+        assert_eq!(params.safesearch, Some(1));
+        assert_eq!(params.categories.as_deref(), Some("images"));
+        assert_eq!(params.pageno, None);
+    }
+
+    #[test]
+    fn validate_test() {
+        assert!(SearchParams::new("rust").validate().is_ok());
+        let valid = SearchParams {
+            pageno: Some(2),
+            categories: Some("general,it".to_string()),
+            language: Some("pt-BR".to_string()),
+            time_range: Some("month".to_string()),
+            safesearch: Some(2),
+            engines: Some("google images,bing".to_string()),
+            format: Some("json".to_string()),
+            ..SearchParams::new("rust")
+        };
+        assert!(valid.validate().is_ok());
+
+        let invalid = [
+            SearchParams {
+                pageno: Some(0),
+                ..SearchParams::new("rust")
+            },
+            SearchParams {
+                time_range: Some("decade".to_string()),
+                ..SearchParams::new("rust")
+            },
+            SearchParams {
+                safesearch: Some(3),
+                ..SearchParams::new("rust")
+            },
+            SearchParams {
+                format: Some("xml".to_string()),
+                ..SearchParams::new("rust")
+            },
+            SearchParams {
+                language: Some("en&x=1".to_string()),
+                ..SearchParams::new("rust")
+            },
+            SearchParams {
+                categories: Some("general,,it".to_string()),
+                ..SearchParams::new("rust")
+            },
+        ];
+        for params in invalid {
+            assert!(params.validate().is_err(), "{params:?}");
+        }
+    }
+
+    #[test]
+    fn append_to_test() {
+        let params = SearchParams {
+            pageno: Some(3),
+            categories: Some("general,it".to_string()),
+            time_range: Some("week".to_string()),
+            safesearch: Some(0),
+            ..SearchParams::new("rust & c++")
+        };
+        let mut url = Url::parse("https://searx.be/search").unwrap();
+        params.append_to(&mut url);
+        assert_eq!(
+            url.as_str(),
+            "https://searx.be/search?q=rust+%26+c%2B%2B&pageno=3&categories=general%2Cit&time_range=week&safesearch=0"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub content: String,
+    pub engines: Vec<String>,
+    pub score: f64,
+    pub category: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SearchResponse {
+    pub query: String,
+    pub instance: String,
+    pub results: Vec<SearchResult>,
+}
+
+/// Result as returned by an instance for `format=json`. Every field is optional
+/// so that instances running older or patched versions still deserialize.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RawSearchResult {
+    title: Option<String>,
+    url: Option<String>,
+    content: Option<String>,
+    engine: Option<String>,
+    engines: Option<Vec<String>>,
+    score: Option<f64>,
+    category: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RawSearchResponse {
+    query: Option<String>,
+    results: Vec<RawSearchResult>,
+}
+
+impl From<RawSearchResult> for SearchResult {
+    fn from(raw: RawSearchResult) -> Self {
+        let engines = match (raw.engines, raw.engine) {
+            (Some(engines), _) if !engines.is_empty() => engines,
+            (_, Some(engine)) => vec![engine],
+            _ => Vec::new(),
+        };
+        Self {
+            title: raw.title.unwrap_or_default(),
+            url: raw.url.unwrap_or_default(),
+            content: raw.content.unwrap_or_default(),
+            engines,
+            score: raw.score.unwrap_or_default(),
+            category: raw.category.unwrap_or_default(),
+        }
+    }
+}
+
+impl SearchResponse {
+    pub fn from_raw(raw: RawSearchResponse, query: &str, instance: &str) -> Self {
+        Self {
+            query: raw.query.unwrap_or_else(|| query.to_string()),
+            instance: instance.to_string(),
+            results: raw
+                .results
+                .into_iter()
+                .map(SearchResult::from)
+                .filter(|result| !result.url.is_empty())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn from_raw_test() {
+        let raw = json!({
+            "query": "rust",
+            "number_of_results": 0,
+            "results": [
+                {
+                    "url": "https://www.rust-lang.org/",
+                    "title": "Rust Programming Language",
+                    "content": "A language empowering everyone",
+                    "engine": "duckduckgo",
+                    "engines": ["duckduckgo", "google"],
+                    "score": 4.5,
+                    "category": "general",
+                    "positions": [1, 1]
+                },
+                {
+                    "url": "https://doc.rust-lang.org/",
+                    "title": "Docs",
+                    "content": null,
+                    "engine": "bing"
+                },
+                {
+                    "title": "no url"
+                }
+            ],
+            "answers": [],
+            "suggestions": []
+        });
+        let raw: RawSearchResponse = serde_json::from_value(raw).unwrap();
+        let response = SearchResponse::from_raw(raw, "ignored", "https://searx.be/");
+        assert_eq!(response.query, "rust");
+        assert_eq!(response.instance, "https://searx.be/");
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(
+            response.results[0],
+            SearchResult {
+                title: "Rust Programming Language".to_string(),
+                url: "https://www.rust-lang.org/".to_string(),
+                content: "A language empowering everyone".to_string(),
+                engines: vec!["duckduckgo".to_string(), "google".to_string()],
+                score: 4.5,
+                category: "general".to_string(),
+            }
+        );
+        assert_eq!(response.results[1].engines, vec!["bing".to_string()]);
+        assert_eq!(response.results[1].content, "");
+    }
+}
@@ -1,130 +1,256 @@
+use std::{collections::HashMap, pin::Pin};
+
 #[cfg(test)]
 use mockall::automock;
 
-use anyhow::anyhow;
+use actix_web::web::Bytes;
 use async_trait::async_trait;
-use log::info;
+use futures_util::{stream, Stream};
+use log::{debug, info};
+use rand::thread_rng;
 use reqwest::{
     header::{self, HeaderMap, HeaderValue},
-    Client, Url,
+    Client, StatusCode, Url,
+};
+
+use crate::{
+    fingerprint::FingerprintConfig,
+    instance_source::{fetch_all, Fetched, InstanceList, InstanceSource, SourceConfig, Validators},
+    proxy::{network_type, ProxyConfig},
+    search_params::SearchParams,
+    search_results::{RawSearchResponse, SearchResponse},
 };
-use serde_json::{Map, Value};
 
-#[derive(Debug)]
+/// Response body as it arrives from the instance.
+pub type BodyStream = Pin<Box<dyn Stream<Item = anyhow::Result<Bytes>> + Send>>;
+
 pub struct SearxClient {
     http_client: Client,
-    base_url: Url,
+    network_clients: HashMap<String, Client>,
+    sources: Vec<Box<dyn InstanceSource>>,
+    fingerprint: FingerprintConfig,
 }
 
-static AGENT: &str =
-    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:103.0) Gecko/20100101 Firefox/103.0";
-
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait SearxProvider: Sync + Send {
-    async fn fetch_instances(&self) -> anyhow::Result<Map<String, Value>>;
+    async fn fetch_instances(
+        &self,
+        validators: &HashMap<String, Validators>,
+    ) -> anyhow::Result<Fetched<InstanceList>>;
     async fn get_instance_search_body(
         &self,
         instance_url: &str,
-        query: &str,
+        params: &SearchParams,
     ) -> anyhow::Result<String>;
+    async fn get_instance_search_stream(
+        &self,
+        instance_url: &str,
+        params: &SearchParams,
+    ) -> anyhow::Result<BodyStream>;
+    async fn get_instance_search_json(
+        &self,
+        instance_url: &str,
+        params: &SearchParams,
+    ) -> anyhow::Result<SearchResponse>;
 }
 
 #[async_trait]
 impl SearxProvider for SearxClient {
-    async fn fetch_instances(&self) -> anyhow::Result<Map<String, Value>> {
+    async fn fetch_instances(
+        &self,
+        validators: &HashMap<String, Validators>,
+    ) -> anyhow::Result<Fetched<InstanceList>> {
         info!("start fetching");
-        let url = Url::join(&self.base_url, "data/instances.json")?;
-        let body: Value = self.http_client.get(url).send().await?.json().await?;
+        let instances = fetch_all(&self.sources, validators).await?;
         info!("end of fetching");
-        let instances: Map<String, Value> = body["instances"]
-            .as_object()
-            .ok_or_else(|| anyhow!("no instances prop"))?
-            .clone();
         Ok(instances)
     }
+    /// Error statuses fail, see [`failure_kind`] for what they say about the instance.
     async fn get_instance_search_body(
         &self,
         instance_url: &str,
-        query: &str,
+        params: &SearchParams,
     ) -> anyhow::Result<String> {
-        let url = get_instance_search_url(instance_url, query);
-        let mut headers = HeaderMap::new();
-        url.host_str()
-            .map(|url| {
-                headers.insert(header::HOST, HeaderValue::from_str(url).unwrap());
-            })
-            .unwrap_or_default();
-        headers.insert(header::USER_AGENT, HeaderValue::from_str(AGENT).unwrap());
-        headers.insert(header::TRANSFER_ENCODING, header::TRAILER.into());
-
-        headers.insert(header::ACCEPT, HeaderValue::from_str("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8").unwrap());
-        headers.insert(
-            header::ACCEPT_LANGUAGE,
-            HeaderValue::from_str("pl,en-US;q=0.7,en;q=0.3").unwrap(),
-        );
-        // headers.insert(
-        //         header::ACCEPT_LANGUAGE,
-        //         HeaderValue::from_str("en-US,en;q=0.5").unwrap(),
-        //     );
-        let body = self
-            .http_client
+        let url = get_instance_search_url(instance_url, params)?;
+        let headers = self.instance_headers(instance_url, params);
+        let response = self
+            .client_for(instance_url)
             .get(url)
             .headers(headers)
             // .header("Connection", "keep")
             .send()
             .await?
-            .text()
+            .error_for_status()?;
+        let body = response.text().await?;
+        Ok(body)
+    }
+    /// Like `get_instance_search_body`, without waiting for the whole page. Errors while
+    /// reading the body end the stream.
+    async fn get_instance_search_stream(
+        &self,
+        instance_url: &str,
+        params: &SearchParams,
+    ) -> anyhow::Result<BodyStream> {
+        let url = get_instance_search_url(instance_url, params)?;
+        let headers = self.instance_headers(instance_url, params);
+        let response = self
+            .client_for(instance_url)
+            .get(url)
+            .headers(headers)
+            .send()
+            .await?
+            .error_for_status()?;
+        let body = stream::unfold(Some(response), |response| async move {
+            let mut response = response?;
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err.into()), None)),
+            }
+        });
+        Ok(Box::pin(body))
+    }
+    async fn get_instance_search_json(
+        &self,
+        instance_url: &str,
+        params: &SearchParams,
+    ) -> anyhow::Result<SearchResponse> {
+        let params = SearchParams {
+            format: Some("json".to_string()),
+            ..params.clone()
+        };
+        let url = get_instance_search_url(instance_url, &params)?;
+        let mut headers = self.instance_headers(instance_url, &params);
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let raw: RawSearchResponse = self
+            .client_for(instance_url)
+            .get(url)
+            .headers(headers)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
             .await?;
-        Ok(convert_html_urls_to_absolute(body, instance_url))
+        Ok(SearchResponse::from_raw(raw, &params.q, instance_url))
     }
 }
 
 impl SearxClient {
-    pub fn new(base_url: String) -> Self {
-        Self {
-            base_url: Url::parse(&base_url).unwrap(),
-            http_client: Client::new(),
-        }
+    pub fn new(
+        sources: &[SourceConfig],
+        fingerprint: FingerprintConfig,
+        proxy: &ProxyConfig,
+    ) -> anyhow::Result<Self> {
+        let (http_client, network_clients) = proxy.build_clients()?;
+        let sources = sources
+            .iter()
+            .map(|source| source.build(&http_client))
+            .collect::<anyhow::Result<Vec<Box<dyn InstanceSource>>>>()?;
+        Ok(Self {
+            sources,
+            http_client,
+            network_clients,
+            fingerprint,
+        })
+    }
+
+    fn client_for(&self, instance_url: &str) -> &Client {
+        self.network_clients
+            .get(network_type(instance_url))
+            .unwrap_or(&self.http_client)
+    }
+
+    fn instance_headers(&self, instance_url: &str, params: &SearchParams) -> HeaderMap {
+        let profile = self.fingerprint.pick(instance_url, &mut thread_rng());
+        debug!("using header profile {} for {instance_url}", profile.name);
+        self.fingerprint
+            .headers(&profile, params.accept_language.as_deref())
     }
 }
 
-fn convert_html_urls_to_absolute(body: String, url: &str) -> String {
-    body.replace("href=\"/", &format!("href=\"{}", url))
-        .replace("src=\"/", &format!("src=\"{}", url))
-        .replace("\"/search", &format!("\"{}search", url))
+/// What a failed instance request says about the instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The instance is down: timeouts, refused connections and server errors.
+    Outage,
+    /// The instance turned rsearx away with a 403 or 429, it may well serve others.
+    Refusal,
+    /// Anything else, e.g. a 404 or a page that doesn't parse.
+    Other,
+}
+
+pub fn failure_kind(err: &anyhow::Error) -> FailureKind {
+    let err = match err.downcast_ref::<reqwest::Error>() {
+        Some(err) => err,
+        // e.g. the caller's own timeout
+        None => return FailureKind::Outage,
+    };
+    match err.status() {
+        Some(StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS) => FailureKind::Refusal,
+        Some(status) if status.is_server_error() => FailureKind::Outage,
+        Some(_) => FailureKind::Other,
+        None if err.is_timeout() || err.is_connect() => FailureKind::Outage,
+        None => FailureKind::Other,
+    }
 }
 
-fn get_instance_search_url(instance_url: &str, query: &str) -> Url {
-    let search_route = format!("/search?q={}", query);
-    let url = Url::parse(instance_url)
-        .unwrap()
-        .join(&search_route)
-        .unwrap();
+fn get_instance_search_url(instance_url: &str, params: &SearchParams) -> anyhow::Result<Url> {
+    let mut url = Url::parse(instance_url)?.join("search")?;
+    params.append_to(&mut url);
     info!("instance full url {url}");
 
-    url
+    Ok(url)
 }
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use anyhow::anyhow;
+
     use super::*;
+
+    /// The error the client returns for a response with `status`.
+    pub(crate) fn status_error(status: u16) -> anyhow::Error {
+        let response = http::Response::builder().status(status).body("").unwrap();
+        reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err()
+            .into()
+    }
+
     #[test]
-    fn convert_html_urls_to_absolute_test() {
-        let body =
-            r#"<a href="/lola" /> <img src="/heheszki.jpg" /> <form action="/search"></form> "#
-                .to_string();
-        let after = convert_html_urls_to_absolute(body, "https://test.com/");
-        assert_eq!(
-            after,
-            r#"<a href="https://test.com/lola" /> <img src="https://test.com/heheszki.jpg" /> <form action="https://test.com/search"></form> "#
-        );
+    fn failure_kind_test() {
+        assert_eq!(failure_kind(&status_error(503)), FailureKind::Outage);
+        assert_eq!(failure_kind(&status_error(403)), FailureKind::Refusal);
+        assert_eq!(failure_kind(&status_error(429)), FailureKind::Refusal);
+        assert_eq!(failure_kind(&status_error(404)), FailureKind::Other);
+        assert_eq!(failure_kind(&anyhow!("timed out")), FailureKind::Outage);
     }
     #[test]
     fn get_insance_search_url_test() {
         let instance = "http://searx.jp/";
-        let query = "semaphore";
+        let params = SearchParams::new("semaphore");
         let expected_url = "http://searx.jp/search?q=semaphore";
-        let result = get_instance_search_url(instance, query).to_string();
+        let result = get_instance_search_url(instance, &params)
+            .unwrap()
+            .to_string();
         assert_eq!(result, expected_url);
+
+        let instance = "https://example.org/searx/";
+        let params = SearchParams {
+            pageno: Some(2),
+            format: Some("json".to_string()),
+            ..SearchParams::new("a b")
+        };
+        let expected_url = "https://example.org/searx/search?q=a+b&pageno=2&format=json";
+        let result = get_instance_search_url(instance, &params)
+            .unwrap()
+            .to_string();
+        assert_eq!(result, expected_url);
+    }
+
+    #[test]
+    fn get_instance_search_url_invalid_test() {
+        let params = SearchParams::new("semaphore");
+        assert!(get_instance_search_url("searx.jp", &params).is_err());
     }
 }
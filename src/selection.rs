@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use crate::{
+    grade::HtmlGrade,
+    health::InstanceHealth,
+    instance_info::{InstanceInfo, TimedRequest},
+};
+#[cfg(test)]
+use mock_instant::Instant;
+use rand::{seq::SliceRandom, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    Uniform,
+    /// Random, favouring well graded instances that answer fast and reliably.
+    #[default]
+    Weighted,
+    RoundRobin,
+    LeastRecentlyUsed,
+    /// The faster of two random instances, by observed latency.
+    PowerOfTwoChoices,
+}
+
+impl Strategy {
+    pub fn build(&self) -> Box<dyn SelectionStrategy> {
+        match self {
+            Strategy::Uniform => Box::new(Uniform),
+            Strategy::Weighted => Box::new(Weighted),
+            Strategy::RoundRobin => Box::new(RoundRobin),
+            Strategy::LeastRecentlyUsed => Box::new(LeastRecentlyUsed),
+            Strategy::PowerOfTwoChoices => Box::new(PowerOfTwoChoices),
+        }
+    }
+}
+
+/// Selection bookkeeping shared by all strategies.
+#[derive(Debug, Default)]
+pub struct SelectionState {
+    next: usize,
+    last_used: HashMap<String, Instant>,
+}
+
+impl SelectionState {
+    pub fn mark_used(&mut self, url: &str) {
+        self.last_used.insert(url.to_string(), Instant::now());
+    }
+}
+
+pub struct SelectionContext<'a> {
+    pub published: &'a HashMap<String, PublishedStats>,
+    pub health: &'a HashMap<String, InstanceHealth>,
+    pub state: &'a mut SelectionState,
+}
+
+pub trait SelectionStrategy {
+    /// Index of the chosen one of `candidates`, which is never empty.
+    fn select(
+        &self,
+        candidates: &[&String],
+        context: &mut SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize;
+}
+
+pub struct Uniform;
+pub struct Weighted;
+pub struct RoundRobin;
+pub struct LeastRecentlyUsed;
+pub struct PowerOfTwoChoices;
+
+impl SelectionStrategy for Uniform {
+    fn select(
+        &self,
+        candidates: &[&String],
+        _: &mut SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        rng.gen_range(0..candidates.len())
+    }
+}
+
+impl SelectionStrategy for Weighted {
+    fn select(
+        &self,
+        candidates: &[&String],
+        context: &mut SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        let indexes: Vec<usize> = (0..candidates.len()).collect();
+        indexes
+            .choose_weighted(rng, |index| {
+                let url = candidates[*index];
+                instance_weight(context.published.get(url), context.health.get(url))
+            })
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl SelectionStrategy for RoundRobin {
+    fn select(
+        &self,
+        candidates: &[&String],
+        context: &mut SelectionContext,
+        _: &mut dyn RngCore,
+    ) -> usize {
+        let index = context.state.next % candidates.len();
+        context.state.next = context.state.next.wrapping_add(1);
+        index
+    }
+}
+
+impl SelectionStrategy for LeastRecentlyUsed {
+    /// Instances that were never used come first, in pool order.
+    fn select(
+        &self,
+        candidates: &[&String],
+        context: &mut SelectionContext,
+        _: &mut dyn RngCore,
+    ) -> usize {
+        (0..candidates.len())
+            .min_by_key(|index| context.state.last_used.get(candidates[*index]))
+            .unwrap_or_default()
+    }
+}
+
+impl SelectionStrategy for PowerOfTwoChoices {
+    /// The duel goes to the lower observed latency divided by the success rate. Instances
+    /// that were never tried win, so every instance gets measured, while instances that
+    /// only ever failed lose.
+    fn select(
+        &self,
+        candidates: &[&String],
+        context: &mut SelectionContext,
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        let indexes: Vec<usize> = (0..candidates.len()).collect();
+        let cost = |index: &usize| match context.health.get(candidates[*index]) {
+            Some(health) => match health.latency {
+                Some(latency) => {
+                    latency.as_secs_f64() / health.success_rate.unwrap_or(1.0).max(MIN_SUCCESS_RATE)
+                }
+                None => f64::INFINITY,
+            },
+            None => 0.0,
+        };
+        indexes
+            .choose_multiple(rng, 2)
+            .min_by(|a, b| cost(a).total_cmp(&cost(b)))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Grade factor of instances without searx.space data, e.g. pinned ones.
+const DEFAULT_GRADE_FACTOR: f64 = 0.5;
+/// Keeps instances that failed a lot selectable, so they can recover.
+const MIN_SUCCESS_RATE: f64 = 0.05;
+
+/// What searx.space says about an instance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PublishedStats {
+    pub grade_factor: f64,
+    /// Mean search time in seconds.
+    pub search_mean: Option<f64>,
+}
+
+impl PublishedStats {
+    pub fn from_info(info: &InstanceInfo) -> Self {
+        let grade = info.html_grade().map(str::parse::<HtmlGrade>);
+        let grade_factor = match grade {
+            Some(Ok(HtmlGrade::V)) => 1.0,
+            Some(Ok(HtmlGrade::C)) => 0.8,
+            Some(Ok(HtmlGrade::Cjs)) => 0.5,
+            Some(Ok(HtmlGrade::E)) => 0.3,
+            _ => DEFAULT_GRADE_FACTOR,
+        };
+        Self {
+            grade_factor,
+            search_mean: info
+                .timing(TimedRequest::Search)
+                .and_then(|stats| stats.mean()),
+        }
+    }
+}
+
+/// Grade factor times success rate, divided by one plus the response time in seconds.
+/// Response times measured from here beat the searx.space means, which are taken from
+/// elsewhere.
+pub fn instance_weight(published: Option<&PublishedStats>, health: Option<&InstanceHealth>) -> f64 {
+    let grade_factor = published.map_or(DEFAULT_GRADE_FACTOR, |stats| stats.grade_factor);
+    let latency = health
+        .and_then(|health| health.latency)
+        .map(|latency| latency.as_secs_f64())
+        .or_else(|| published.and_then(|stats| stats.search_mean))
+        .unwrap_or(1.0);
+    let success_rate = health
+        .and_then(|health| health.success_rate)
+        .unwrap_or(1.0)
+        .max(MIN_SUCCESS_RATE);
+    grade_factor * success_rate / (1.0 + latency.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mock_instant::MockClock;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    use super::*;
+
+    fn pool() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    fn pick_many(strategy: Strategy, context: &mut SelectionContext, rolls: usize) -> Vec<String> {
+        let pool = pool();
+        let candidates: Vec<&String> = pool.iter().collect();
+        let strategy = strategy.build();
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..rolls)
+            .map(|_| {
+                let url = candidates[strategy.select(&candidates, context, &mut rng)];
+                context.state.mark_used(url);
+                MockClock::advance(Duration::from_secs(1));
+                url.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uniform_is_deterministic_under_seed_test() {
+        let (published, health) = (HashMap::new(), HashMap::new());
+        let mut state = SelectionState::default();
+        let mut context = SelectionContext {
+            published: &published,
+            health: &health,
+            state: &mut state,
+        };
+        let first = pick_many(Strategy::Uniform, &mut context, 20);
+        let second = pick_many(Strategy::Uniform, &mut context, 20);
+        assert_eq!(first, second);
+        assert!(pool().iter().all(|url| first.contains(url)));
+    }
+
+    #[test]
+    fn round_robin_and_lru_test() {
+        let (published, health) = (HashMap::new(), HashMap::new());
+        let mut state = SelectionState::default();
+        let mut context = SelectionContext {
+            published: &published,
+            health: &health,
+            state: &mut state,
+        };
+        assert_eq!(
+            pick_many(Strategy::RoundRobin, &mut context, 4),
+            vec!["a", "b", "c", "a"]
+        );
+        // "a" was used last, so "b" is the least recently used one
+        assert_eq!(
+            pick_many(Strategy::LeastRecentlyUsed, &mut context, 3),
+            vec!["b", "c", "a"]
+        );
+    }
+
+    #[test]
+    fn weighted_test() {
+        let mut published = HashMap::new();
+        for (url, grade_factor) in [("a", 1.0), ("b", 0.0), ("c", 0.0)] {
+            let stats = PublishedStats {
+                grade_factor,
+                search_mean: None,
+            };
+            published.insert(url.to_string(), stats);
+        }
+        let health = HashMap::new();
+        let mut state = SelectionState::default();
+        let mut context = SelectionContext {
+            published: &published,
+            health: &health,
+            state: &mut state,
+        };
+        assert!(pick_many(Strategy::Weighted, &mut context, 10)
+            .iter()
+            .all(|url| url == "a"));
+    }
+
+    #[test]
+    fn power_of_two_choices_test() {
+        let published = HashMap::new();
+        let mut health = HashMap::new();
+        for (url, millis) in [("a", 900), ("b", 100), ("c", 500)] {
+            let mut instance = InstanceHealth::default();
+            instance.record_success(Some(Duration::from_millis(millis)));
+            health.insert(url.to_string(), instance);
+        }
+        let mut state = SelectionState::default();
+        let mut context = SelectionContext {
+            published: &published,
+            health: &health,
+            state: &mut state,
+        };
+        // the slowest instance can never win a duel
+        assert!(
+            !pick_many(Strategy::PowerOfTwoChoices, &mut context, 30).contains(&"a".to_string())
+        );
+    }
+
+    #[test]
+    fn power_of_two_choices_failed_only_test() {
+        let published = HashMap::new();
+        let mut health = HashMap::new();
+        let mut failed = InstanceHealth::default();
+        failed.record_failure(
+            "timeout".to_string(),
+            &crate::health::BreakerConfig::default(),
+        );
+        health.insert("a".to_string(), failed);
+        for (url, millis) in [("b", 900), ("c", 500)] {
+            let mut instance = InstanceHealth::default();
+            instance.record_success(Some(Duration::from_millis(millis)));
+            health.insert(url.to_string(), instance);
+        }
+        let mut state = SelectionState::default();
+        let mut context = SelectionContext {
+            published: &published,
+            health: &health,
+            state: &mut state,
+        };
+        assert!(
+            !pick_many(Strategy::PowerOfTwoChoices, &mut context, 30).contains(&"a".to_string())
+        );
+    }
+
+    #[test]
+    fn instance_weight_test() {
+        let stats = |record| PublishedStats::from_info(&serde_json::from_value(record).unwrap());
+        let fast = stats(
+            json!({ "html": { "grade": "V" }, "timing": { "search": { "all": { "mean": 0.25 } } } }),
+        );
+        let slow = stats(
+            json!({ "html": { "grade": "V" }, "timing": { "search": { "all": { "mean": 3.0 } } } }),
+        );
+        let poor = stats(
+            json!({ "html": { "grade": "E" }, "timing": { "search": { "all": { "mean": 0.25 } } } }),
+        );
+        assert!(instance_weight(Some(&fast), None) > instance_weight(Some(&slow), None));
+        assert!(instance_weight(Some(&fast), None) > instance_weight(Some(&poor), None));
+        assert_eq!(instance_weight(None, None), DEFAULT_GRADE_FACTOR / 2.0);
+
+        // measured from here, the globally slow instance is the fast one
+        let config = crate::health::BreakerConfig::default();
+        let mut near = InstanceHealth::default();
+        near.record_success(Some(Duration::from_millis(100)));
+        let mut far = InstanceHealth::default();
+        far.record_success(Some(Duration::from_millis(2000)));
+        assert!(
+            instance_weight(Some(&slow), Some(&near)) > instance_weight(Some(&fast), Some(&far))
+        );
+        far.record_success(Some(Duration::from_millis(100)));
+        far.record_failure("timeout".to_string(), &config);
+        assert!(
+            instance_weight(Some(&slow), Some(&near)) > instance_weight(Some(&slow), Some(&far))
+        );
+    }
+}
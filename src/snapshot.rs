@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::fs;
+
+use crate::instance_source::{InstanceList, Validators};
+
+pub static SNAPSHOT_FILENAME: &str = "instances_snapshot.json";
+
+/// Last successfully fetched instance list with all of its metadata,
+/// used when the sources can't be reached.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Snapshot {
+    /// Unix time in seconds.
+    pub fetched_at: u64,
+    pub instances: Map<String, Value>,
+    /// Validators by source name, so a restart can still fetch conditionally.
+    #[serde(default)]
+    pub validators: HashMap<String, Validators>,
+}
+
+impl Snapshot {
+    pub fn new(list: InstanceList) -> Self {
+        Self {
+            fetched_at: unix_secs(SystemTime::now()),
+            instances: list.instances,
+            validators: list.validators,
+        }
+    }
+
+    /// Writes to a temporary file first and renames it, so a crash never leaves
+    /// a half written snapshot behind.
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read(path).await?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    pub fn fetched_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.fetched_at)
+    }
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn age_secs(fetched_at: SystemTime) -> u64 {
+    SystemTime::now()
+        .duration_since(fetched_at)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[actix_rt::test]
+    async fn save_and_load_test() {
+        let dir = std::env::temp_dir().join(format!("rsearx-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(SNAPSHOT_FILENAME);
+        let mut instances = Map::new();
+        instances.insert(
+            "https://a.org/".to_string(),
+            json!({ "html": { "grade": "V" }, "network_type": "normal" }),
+        );
+        let mut validators = HashMap::new();
+        validators.insert(
+            "https://searx.space/".to_string(),
+            Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            },
+        );
+        let snapshot = Snapshot::new(InstanceList {
+            instances,
+            validators,
+        });
+        snapshot.save(&path).await.unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        let loaded = Snapshot::load(&path).await.unwrap();
+        assert_eq!(loaded, snapshot);
+        assert!(age_secs(loaded.fetched_at()) < 60);
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert!(Snapshot::load(&path).await.is_err());
+    }
+
+    #[test]
+    fn load_without_validators_test() {
+        let snapshot: Snapshot =
+            serde_json::from_str(r#"{ "fetched_at": 0, "instances": {} }"#).unwrap();
+        assert!(snapshot.validators.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt, ops::Bound};
+
+/// Instance version as published by searx.space. Classic searx uses semver
+/// (`1.1.0`, `1.0.0-1234-abcdef`), SearXNG uses dates (`2024.10.17+e7a2b6f`,
+/// `2023.3.24-4b0ffe9e`). Every classic searx version sorts below every SearXNG one.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Searx(u64, u64, u64),
+    Searxng(u64, u64, u64),
+}
+
+/// SearXNG versions start with the year.
+const FIRST_SEARXNG_YEAR: u64 = 2000;
+
+impl Version {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let text = text.strip_prefix(['v', 'V']).unwrap_or(text);
+        // drop the commit or build suffix
+        let core = text.split(['+', '-', ' ']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse::<u64>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        if parts.next().is_some() {
+            return None;
+        }
+        if major >= FIRST_SEARXNG_YEAR {
+            Some(Version::Searxng(major, minor, patch))
+        } else {
+            Some(Version::Searx(major, minor, patch))
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Searx(major, minor, patch) => write!(f, "searx {major}.{minor}.{patch}"),
+            Version::Searxng(year, month, day) => write!(f, "SearXNG {year}.{month}.{day}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionRange {
+    min: Bound<Version>,
+    max: Bound<Version>,
+}
+
+impl VersionRange {
+    /// Bounds are inclusive unless written as `>1.1.0` or `<2024.1.1`; `>=` and `<=` are
+    /// accepted too. An empty bound is unbounded.
+    pub fn parse(min: &str, max: &str) -> Result<Self, String> {
+        let min = parse_bound(min, ">")?;
+        let max = parse_bound(max, "<")?;
+        let range = Self { min, max };
+        if range.is_empty() {
+            return Err(format!("version range {range} matches no version"));
+        }
+        Ok(range)
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        let above_min = match &self.min {
+            Bound::Included(min) => version >= min,
+            Bound::Excluded(min) => version > min,
+            Bound::Unbounded => true,
+        };
+        let below_max = match &self.max {
+            Bound::Included(max) => version <= max,
+            Bound::Excluded(max) => version < max,
+            Bound::Unbounded => true,
+        };
+        above_min && below_max
+    }
+
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (Bound::Included(min), Bound::Included(max)) => min > max,
+            (Bound::Included(min) | Bound::Excluded(min), Bound::Excluded(max))
+            | (Bound::Excluded(min), Bound::Included(max)) => min >= max,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.min {
+            Bound::Included(min) => write!(f, "[{min}, ")?,
+            Bound::Excluded(min) => write!(f, "({min}, ")?,
+            Bound::Unbounded => write!(f, "(-, ")?,
+        }
+        match &self.max {
+            Bound::Included(max) => write!(f, "{max}]"),
+            Bound::Excluded(max) => write!(f, "{max})"),
+            Bound::Unbounded => write!(f, "-)"),
+        }
+    }
+}
+
+fn parse_bound(text: &str, exclusive: &str) -> Result<Bound<Version>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Bound::Unbounded);
+    }
+    let inclusive = format!("{exclusive}=");
+    let (version, is_exclusive) = match text.strip_prefix(&inclusive) {
+        Some(version) => (version, false),
+        None => match text.strip_prefix(exclusive) {
+            Some(version) => (version, true),
+            None => (text, false),
+        },
+    };
+    let version =
+        Version::parse(version).ok_or_else(|| format!("invalid version bound {text:?}"))?;
+    Ok(if is_exclusive {
+        Bound::Excluded(version)
+    } else {
+        Bound::Included(version)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Version::parse("1.1.0"), Some(Version::Searx(1, 1, 0)));
+        assert_eq!(
+            Version::parse("v1.0.0-1234-abcdef"),
+            Some(Version::Searx(1, 0, 0))
+        );
+        assert_eq!(
+            Version::parse("2024.10.17+e7a2b6f"),
+            Some(Version::Searxng(2024, 10, 17))
+        );
+        assert_eq!(
+            Version::parse("2023.3.24-4b0ffe9e"),
+            Some(Version::Searxng(2023, 3, 24))
+        );
+        assert_eq!(Version::parse("1.0"), Some(Version::Searx(1, 0, 0)));
+        assert_eq!(Version::parse("unknown"), None);
+        assert_eq!(Version::parse("1.2.3.4"), None);
+        assert!(Version::Searx(99, 0, 0) < Version::Searxng(2020, 1, 1));
+        assert!(Version::Searxng(2023, 12, 31) < Version::Searxng(2024, 1, 1));
+    }
+
+    #[test]
+    fn range_test() {
+        let range = VersionRange::parse("1.1.0", "<2024.1.1").unwrap();
+        assert!(range.contains(&Version::Searx(1, 1, 0)));
+        assert!(!range.contains(&Version::Searx(1, 0, 9)));
+        assert!(range.contains(&Version::Searxng(2023, 12, 31)));
+        assert!(!range.contains(&Version::Searxng(2024, 1, 1)));
+
+        let range = VersionRange::parse(">2023.1.1", "").unwrap();
+        assert!(!range.contains(&Version::Searxng(2023, 1, 1)));
+        assert!(range.contains(&Version::Searxng(2025, 1, 1)));
+
+        let range = VersionRange::parse(">=2023.1.1", "<=2023.1.1").unwrap();
+        assert!(range.contains(&Version::Searxng(2023, 1, 1)));
+    }
+
+    #[test]
+    fn invalid_range_test() {
+        assert!(VersionRange::parse("2024.1.1", "1.1.0").is_err());
+        assert!(VersionRange::parse(">2023.1.1", "2023.1.1").is_err());
+        assert!(VersionRange::parse("latest", "").is_err());
+        assert!(VersionRange::parse("", "").is_ok());
+    }
+}